@@ -0,0 +1,183 @@
+use rust_forth_interactive_compiler::{parse_gas_limit, DispatchMode};
+
+pub const USAGE: &str = "Usage: rust-forth-interactive-compiler [OPTIONS] [FILE...]
+
+The user init file ~/.config/rust-forth/init.fth and then init.forth in the
+current directory are loaded first, if they exist. Files are loaded in order
+after that, like the l command.
+
+Options:
+    -e, --eval <forth>      Execute Forth text, can be given more than once
+        --batch             Run the lines read from stdin without a prompt, exit
+                            with a non-zero status on the first error
+        --no-interactive    Exit once the files, -e text and stdin are done
+        --gas <limit>       Gas limit for each execution, a number or unlimited
+    -I, --include-path <dir>
+                            Look in this directory for files named by INCLUDE
+                            and REQUIRE, can be given more than once
+        --no-init           Don't load init.forth or ~/.config/rust-forth/init.fth
+        --command-prefix <c>
+                            Start REPL commands with this character instead of \\
+        --commands-only     Treat every line as a REPL command, Forth text is
+                            entered with i
+    -h, --help              Print this help";
+
+/// Something to do before the prompt appears, kept in command line order
+#[derive(Debug, PartialEq)]
+pub enum StartupAction {
+    Load(String),
+    Eval(String),
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub startup: Vec<StartupAction>,
+    pub batch: bool,
+    pub no_interactive: bool,
+    pub gas_limit: Option<Option<u64>>,
+    pub include_path: Vec<String>,
+    pub dispatch_mode: Option<DispatchMode>,
+    pub no_init: bool,
+    pub help: bool,
+}
+
+impl Options {
+    pub fn parse<I>(args: I) -> Result<Options, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-e" | "--eval" => {
+                    let text = args
+                        .next()
+                        .ok_or_else(|| format!("{} needs Forth text", arg))?;
+                    options.startup.push(StartupAction::Eval(text));
+                }
+                "--batch" => options.batch = true,
+                "--no-interactive" => options.no_interactive = true,
+                "--no-init" => options.no_init = true,
+                "--gas" => {
+                    let limit = args
+                        .next()
+                        .ok_or_else(|| "--gas needs a limit or unlimited".to_owned())?;
+                    let gas_limit = parse_gas_limit(&limit).map_err(|err| err.to_string())?;
+                    options.gas_limit = Some(gas_limit);
+                }
+                "-I" | "--include-path" => {
+                    let dir = args
+                        .next()
+                        .ok_or_else(|| format!("{} needs a directory", arg))?;
+                    options.include_path.push(dir);
+                }
+                "--command-prefix" => {
+                    let prefix = args
+                        .next()
+                        .ok_or_else(|| "--command-prefix needs a character".to_owned())?;
+                    let mut chars = prefix.chars();
+                    let command_prefix = match (chars.next(), chars.next()) {
+                        (Some(c), None) if !c.is_whitespace() => c,
+                        _ => {
+                            return Err(format!(
+                                "--command-prefix needs a single character, not `{}`",
+                                prefix
+                            ))
+                        }
+                    };
+                    options.dispatch_mode = Some(DispatchMode::ForthByDefault { command_prefix });
+                }
+                "--commands-only" => options.dispatch_mode = Some(DispatchMode::CommandsOnly),
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("Unknown option {}", arg));
+                }
+                _ => options.startup.push(StartupAction::Load(arg)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Whether to finish with the interactive prompt
+    pub fn interactive(&self) -> bool {
+        !self.batch && !self.no_interactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn startup_actions_keep_their_order() {
+        let options = parse(&["init.fs", "-e", "1 2 ADD", "--eval", "3", "app.fs"]).unwrap();
+        assert_eq!(
+            options.startup,
+            vec![
+                StartupAction::Load("init.fs".to_owned()),
+                StartupAction::Eval("1 2 ADD".to_owned()),
+                StartupAction::Eval("3".to_owned()),
+                StartupAction::Load("app.fs".to_owned()),
+            ]
+        );
+        assert!(options.interactive());
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse(&[
+            "--batch",
+            "--no-init",
+            "--gas",
+            "unlimited",
+            "-I",
+            "lib",
+            "--include-path",
+            "vendor",
+            "--command-prefix",
+            ":",
+        ])
+        .unwrap();
+        assert!(options.batch && options.no_init && !options.interactive());
+        assert_eq!(options.gas_limit, Some(None));
+        assert_eq!(options.include_path, vec!["lib", "vendor"]);
+        assert_eq!(
+            options.dispatch_mode,
+            Some(DispatchMode::ForthByDefault {
+                command_prefix: ':'
+            })
+        );
+
+        let options = parse(&["--gas", "500", "--commands-only", "--no-interactive"]).unwrap();
+        assert_eq!(options.gas_limit, Some(Some(500)));
+        assert_eq!(options.dispatch_mode, Some(DispatchMode::CommandsOnly));
+        assert!(!options.interactive());
+    }
+
+    #[test]
+    fn bad_options_are_errors() {
+        for args in [
+            &["--frobnicate"][..],
+            &["-e"],
+            &["--gas"],
+            &["--gas", "lots"],
+            &["-I"],
+            &["--command-prefix", "ab"],
+            &["--command-prefix", " "],
+        ] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+        // A lone dash is a file name
+        assert_eq!(
+            parse(&["-"]).unwrap().startup,
+            vec![StartupAction::Load("-".to_owned())]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::ForthInteractiveError;
+use crate::session::ReplContext;
+
+pub enum CommandHandled {
+    Handled,
+    NotHandled,
+    /// Ask the session to show help, for one command or all of them
+    ShowHelp(Option<String>),
+}
+
+// Commands are looked up by id in a CommandRegistry and run with the id they were registered under
+pub trait HandleCommand {
+    fn handle_command(
+        &mut self,
+        command_id: &str,
+        parameters: &[&str],
+        ctx: &mut ReplContext,
+    ) -> Result<CommandHandled, ForthInteractiveError>;
+    fn command_id(&self) -> String;
+    fn usage_text(&self) -> String;
+    fn help_text(&self) -> String;
+
+    /// Other names the command can be run by
+    fn aliases(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// A fuller description than help_text, shown by `help <command>`
+    fn long_help(&self) -> Option<String> {
+        None
+    }
+
+    /// Each parameter and what it is for
+    fn parameters(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Example command lines, without the command prefix
+    fn examples(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether the parameters are file names, used for completion
+    fn takes_file_parameters(&self) -> bool {
+        false
+    }
+
+    /// Whether the session should take a snapshot to undo the command with
+    fn undoable(&self) -> bool {
+        true
+    }
+}
+
+type CommandFn<'a> =
+    dyn Fn(&str, &[&str], &mut ReplContext) -> Result<CommandHandled, ForthInteractiveError> + 'a;
+
+pub struct CommandHandler<'a> {
+    command_id: String,
+    usage_text: String,
+    help_text: String,
+    aliases: Vec<String>,
+    long_help: Option<String>,
+    parameters: Vec<(String, String)>,
+    examples: Vec<String>,
+    takes_file_parameters: bool,
+    undoable: bool,
+    to_run: Box<CommandFn<'a>>,
+}
+
+impl<'a> CommandHandler<'a> {
+    pub fn new<C>(command_id: &str, usage_text: &str, help_text: &str, f: C) -> CommandHandler<'a>
+    where
+        C: Fn(&str, &[&str], &mut ReplContext) -> Result<CommandHandled, ForthInteractiveError>
+            + 'a,
+    {
+        CommandHandler {
+            command_id: command_id.to_owned(),
+            usage_text: usage_text.to_owned(),
+            help_text: help_text.to_owned(),
+            aliases: Vec::new(),
+            long_help: None,
+            parameters: Vec::new(),
+            examples: Vec::new(),
+            takes_file_parameters: false,
+            undoable: true,
+            to_run: Box::new(f),
+        }
+    }
+
+    pub fn with_aliases(mut self, aliases: &[&str]) -> CommandHandler<'a> {
+        self.aliases = aliases.iter().map(|a| (*a).to_owned()).collect();
+        self
+    }
+
+    pub fn with_long_help(mut self, long_help: &str) -> CommandHandler<'a> {
+        self.long_help = Some(long_help.to_owned());
+        self
+    }
+
+    pub fn with_parameter(mut self, name: &str, description: &str) -> CommandHandler<'a> {
+        self.parameters
+            .push((name.to_owned(), description.to_owned()));
+        self
+    }
+
+    pub fn with_examples(mut self, examples: &[&str]) -> CommandHandler<'a> {
+        self.examples = examples.iter().map(|e| (*e).to_owned()).collect();
+        self
+    }
+
+    pub fn with_file_parameters(mut self) -> CommandHandler<'a> {
+        self.takes_file_parameters = true;
+        self
+    }
+
+    pub fn not_undoable(mut self) -> CommandHandler<'a> {
+        self.undoable = false;
+        self
+    }
+}
+
+impl<'a> HandleCommand for CommandHandler<'a> {
+    fn handle_command(
+        &mut self,
+        command_id: &str,
+        parameters: &[&str],
+        ctx: &mut ReplContext,
+    ) -> Result<CommandHandled, ForthInteractiveError> {
+        if command_id == self.command_id {
+            return (self.to_run)(self.command_id.as_ref(), parameters, ctx);
+        }
+        Ok(CommandHandled::NotHandled)
+    }
+
+    fn command_id(&self) -> String {
+        self.command_id.clone()
+    }
+
+    fn usage_text(&self) -> String {
+        self.usage_text.clone()
+    }
+
+    fn help_text(&self) -> String {
+        self.help_text.clone()
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        self.aliases.clone()
+    }
+
+    fn long_help(&self) -> Option<String> {
+        self.long_help.clone()
+    }
+
+    fn parameters(&self) -> Vec<(String, String)> {
+        self.parameters.clone()
+    }
+
+    fn examples(&self) -> Vec<String> {
+        self.examples.clone()
+    }
+
+    fn takes_file_parameters(&self) -> bool {
+        self.takes_file_parameters
+    }
+
+    fn undoable(&self) -> bool {
+        self.undoable
+    }
+}
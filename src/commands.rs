@@ -0,0 +1,1153 @@
+use crate::command::{CommandHandled, CommandHandler, HandleCommand};
+use crate::debugger::{BreakLocation, Breakpoint, Debugger, RunUntil, Stopped};
+use crate::dictionary::{callees, callers_of, pretty_definition, word_pattern};
+use crate::error::ForthInteractiveError;
+use crate::export::{definition_source, Export};
+use crate::history::WordVersion;
+use crate::opcode::{disassemble, word_end};
+use crate::profile::{Profile, ProfileColumn};
+use crate::session::{parse_gas_limit, ReplContext};
+use crate::snapshot::Snapshot;
+use crate::stack::NumberBase;
+use crate::trace::Trace;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// The commands every ReplSession starts out with
+pub fn default_handlers() -> Vec<Box<dyn HandleCommand>> {
+    let mut command_handlers: Vec<Box<dyn HandleCommand>> = Vec::new();
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "help",
+            "[command]",
+            "List the commands, or show everything about one of them",
+            |_command_id, params, _ctx| {
+                Ok(CommandHandled::ShowHelp(
+                    params.first().map(|p| (*p).to_owned()),
+                ))
+            },
+        )
+        .with_aliases(&["?"])
+        .with_parameter("command", "The command to describe, a prefix will do")
+        .with_examples(&["help", "help gas", "help list_w"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "l",
+            "file1.fs [file2.fs]",
+            "Load Forth file",
+            |_command_id, params, ctx| {
+                for n in params {
+                    ctx.load_file(n)?;
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_aliases(&["load"])
+        .with_long_help(
+            "Each file is executed a statement at a time with the session gas limit. \
+             Loading stops at the first error, which is reported with the file, line \
+             and column it came from.",
+        )
+        .with_parameter(
+            "file1.fs",
+            "Forth source file to load, relative to the current directory",
+        )
+        .with_examples(&["l init.forth", "l init.forth testrun.fth"])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "watch",
+            "[--fresh] [file1.fs file2.fs|off]",
+            "Reload Forth files whenever they change, or show the files being watched",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => {
+                        let lines: Vec<String> = match ctx.watch.as_ref() {
+                            Some(watch) => {
+                                let files = watch
+                                    .files
+                                    .iter()
+                                    .map(|f| format!("Watching {}", f.path.display()));
+                                let included = watch
+                                    .included
+                                    .iter()
+                                    .map(|f| format!("Watching {} (included)", f.path.display()));
+                                files.chain(included).collect()
+                            }
+                            None => vec!["Not watching any files".to_owned()],
+                        };
+                        for line in lines {
+                            ctx.println(line);
+                        }
+                    }
+                    ["off"] => {
+                        ctx.watch = None;
+                        ctx.println("Stopped watching");
+                    }
+                    _ => {
+                        let (fresh, files) = match params.split_first() {
+                            Some((&"--fresh", files)) => (true, files),
+                            _ => (false, params),
+                        };
+                        if files.is_empty() {
+                            return Ok(CommandHandled::ShowHelp(Some("watch".to_owned())));
+                        }
+                        ctx.watch_files(files, fresh)?;
+                        ctx.println(format!("Watching {}", files.join(", ")));
+                    }
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Files that haven't been loaded yet are loaded straight away. Whenever \
+             one of the files is saved, the next line entered first puts the session \
+             back the way it was before the files were loaded and loads them all \
+             again in the same order, then lists the words that were added (+), \
+             removed (-) or changed (~). A file they INCLUDE or REQUIRE is watched \
+             too, and saving it reloads the files that included it. The number stack \
+             is left as it is. With --fresh they are reloaded into an empty \
+             dictionary instead. If a file fails to load the session is left as it \
+             was.",
+        )
+        .with_parameter("--fresh", "Reload into an empty dictionary")
+        .with_parameter(
+            "file1.fs",
+            "Forth source file to watch, relative to the current directory",
+        )
+        .with_parameter("off", "Stop watching every file")
+        .with_examples(&[
+            "watch lib.fs",
+            "watch --fresh lib.fs app.fs",
+            "watch",
+            "watch off",
+        ])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "include_path",
+            "[dir1 dir2|clear]",
+            "Show or set where INCLUDE and REQUIRE look for files",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => (),
+                    ["clear"] => ctx.include_path.clear(),
+                    dirs => ctx.include_path = dirs.iter().map(PathBuf::from).collect(),
+                }
+                if ctx.include_path.is_empty() {
+                    ctx.println("Include path is empty");
+                    return Ok(CommandHandled::Handled);
+                }
+                let dirs: Vec<String> = ctx
+                    .include_path
+                    .iter()
+                    .map(|dir| format!("    {}", dir.display()))
+                    .collect();
+                ctx.println("Include path:");
+                for dir in dirs {
+                    ctx.println(dir);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "INCLUDE file and REQUIRE file in a loaded file look for the file next \
+             to the file including it first, and then in each of these directories \
+             in order. REQUIRE skips a file that has already been loaded this \
+             session. The --include-path command line option adds to it.",
+        )
+        .with_parameter("dir1", "Directories to look in, replacing the current ones")
+        .with_parameter("clear", "Empty the include path")
+        .with_examples(&["include_path", "include_path lib ../shared/forth"])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "save",
+            "file",
+            "Save the dictionary, compiled opcodes and number stack to a snapshot file",
+            |_command_id, params, ctx| {
+                let path = match params {
+                    [path] => path,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("save".to_owned()))),
+                };
+                Snapshot::capture(&ctx.fc).save(path)?;
+                ctx.println(format!(
+                    "Saved {} words to {}",
+                    ctx.fc.word_addresses.len(),
+                    path
+                ));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "The snapshot is a text file holding every word's definition, address and \
+             opcodes along with the compiled opcodes and the number stack, so a session \
+             can be picked up again with restore without reloading its source.",
+        )
+        .with_parameter("file", "Where to write the snapshot, it is overwritten")
+        .with_examples(&["save session.snapshot"])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "restore",
+            "file",
+            "Replace the session's dictionary and number stack with a saved snapshot",
+            |_command_id, params, ctx| {
+                let path = match params {
+                    [path] => path,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("restore".to_owned()))),
+                };
+                ctx.restore_snapshot(&Snapshot::load(path)?)?;
+                ctx.println(format!(
+                    "Restored {} words from {}",
+                    ctx.fc.word_addresses.len(),
+                    path
+                ));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Everything compiled so far is thrown away. Snapshots written by a different \
+             version of the snapshot format are refused rather than half restored.",
+        )
+        .with_parameter("file", "A snapshot written by save")
+        .with_examples(&["restore session.snapshot"])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "export",
+            "file.fs",
+            "Write every word in the dictionary out as Forth source",
+            |_command_id, params, ctx| {
+                let path = match params {
+                    [path] => path,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("export".to_owned()))),
+                };
+                let export = Export::new(&ctx.fc, &ctx.word_history);
+                std::fs::write(path, export.source())?;
+                for w in export.warnings {
+                    ctx.println(format!("Warning: {}", w));
+                }
+                ctx.println(format!(
+                    "Exported {} words to {}",
+                    ctx.fc.word_definitions.len(),
+                    path
+                ));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Each word is written as a definition, words come after the words they \
+             use so loading the file with l rebuilds the dictionary. A word redefined \
+             after other words used it is written once for each definition they call. \
+             Only definitions are written, the number stack is left out.",
+        )
+        .with_parameter("file.fs", "Where to write the source, it is overwritten")
+        .with_examples(&["export experiments.fs"])
+        .with_file_parameters(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "gas",
+            "[limit|unlimited] [forth text]",
+            "Show or set the gas limit, with Forth text run just that text with the limit",
+            |_command_id, params, ctx| {
+                match params.split_first() {
+                    None => {
+                        ctx.println(format!("Gas limit: {}", ctx.describe_gas_limit()));
+                    }
+                    Some((limit, [])) => {
+                        ctx.gas_limit = parse_gas_limit(limit)?;
+                        ctx.println(format!("Gas limit: {}", ctx.describe_gas_limit()));
+                    }
+                    Some((limit, forth)) => {
+                        let gas_limit = parse_gas_limit(limit)?;
+                        ctx.execute_with_gas(&forth.join(" "), gas_limit)?;
+                    }
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Every opcode executed uses one unit of gas, and execution stops with an \
+             error once the limit is used up. This keeps loops that never end from \
+             hanging the REPL.",
+        )
+        .with_parameter("limit", "A number of opcodes, or unlimited")
+        .with_parameter(
+            "forth text",
+            "Run just this text with the limit, leaving the session limit alone",
+        )
+        .with_examples(&[
+            "gas",
+            "gas 10000",
+            "gas unlimited",
+            "gas 5000 10 BEGIN DEC DUP NOT IF LEAVE THEN AGAIN",
+        ]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "n",
+            "No Parameters",
+            "Print number stack",
+            |_command_id, _params, ctx| {
+                let stack = ctx.describe_stack();
+                ctx.println(stack);
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_aliases(&["stack"])
+        .with_long_help(
+            "The depth is shown first and the top of the stack is the last number \
+             shown, in the base chosen with the base command.",
+        ),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "base",
+            "[dec|hex|bin|char]",
+            "Show or set the base the number stack is shown in",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => (),
+                    [base] => match NumberBase::parse(base) {
+                        Some(base) => ctx.number_base = base,
+                        None => return Ok(CommandHandled::ShowHelp(Some("base".to_owned()))),
+                    },
+                    _ => return Ok(CommandHandled::ShowHelp(Some("base".to_owned()))),
+                }
+                ctx.println(format!("Base: {}", ctx.number_base));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Only changes how numbers are shown, Forth still reads and computes in \
+             decimal. In char, numbers that are printable ASCII are shown as the \
+             character and anything else in decimal.",
+        )
+        .with_parameter("dec", "Decimal, the default")
+        .with_parameter("hex", "Hexadecimal, with a 0x prefix")
+        .with_parameter("bin", "Binary, with a 0b prefix")
+        .with_parameter("char", "Characters")
+        .with_examples(&["base hex", "base"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "show_stack",
+            "[on|off]",
+            "Show the number stack after every line of Forth",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => (),
+                    ["on"] => ctx.show_stack = true,
+                    ["off"] => ctx.show_stack = false,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("show_stack".to_owned()))),
+                }
+                let state = if ctx.show_stack { "on" } else { "off" };
+                ctx.println(format!("Showing the stack after each line: {}", state));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_examples(&["show_stack on", "show_stack off"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "p",
+            "n1 [n2]",
+            "Push numbers on stack",
+            |_command_id, params, ctx| {
+                for n in params {
+                    ctx.fc.sm.st.number_stack.push(n.parse::<i64>()?);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_aliases(&["push"])
+        .with_parameter("n1", "A whole number, pushed before the ones after it")
+        .with_examples(&["p 1 2 3", "p -5"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "list_words",
+            "Enter words you wish to list",
+            "List words that are compiled into memory",
+            |_command_id, params, ctx| {
+                for w in params {
+                    if let Some(offset) = ctx.fc.word_addresses.get(*w) {
+                        ctx.println(format!("Word: {} Location: {}", w, offset));
+                    } else {
+                        ctx.println(format!("Unable to find Word [{}] in dictionary.", w));
+                    }
+                    if let Some(original_forth) = ctx.fc.word_definitions.get(*w) {
+                        ctx.println(format!("Word: {} Forth: {}", w, original_forth));
+                    }
+                    if let Some(opcodes) = ctx.fc.word_opcodes.get(*w) {
+                        ctx.println(format!("Word: {} Opcodes: {:?}", w, opcodes));
+                    }
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Shows where each word starts in the compiled opcodes, the Forth it was \
+             defined with and the opcodes it compiled to.",
+        )
+        .with_parameter(
+            "word",
+            "A word from the dictionary, names are case sensitive",
+        )
+        .with_examples(&["list_words Eric Tamara"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "words",
+            "[pattern]",
+            "List the words in the dictionary and how many opcodes each compiled to",
+            |_command_id, params, ctx| {
+                let pattern = match params {
+                    [] => None,
+                    [pattern] => Some(word_pattern(pattern)?),
+                    _ => return Ok(CommandHandled::ShowHelp(Some("words".to_owned()))),
+                };
+
+                let mut words: Vec<&String> = ctx
+                    .fc
+                    .word_addresses
+                    .keys()
+                    .filter(|w| pattern.as_ref().map(|p| p.is_match(w)).unwrap_or(true))
+                    .collect();
+                words.sort();
+                let width = words.iter().map(|w| w.chars().count()).max().unwrap_or(0);
+
+                let mut lines: Vec<String> = words
+                    .iter()
+                    .map(|w| {
+                        let opcodes = ctx.fc.word_opcodes.get(*w).map(|o| o.len()).unwrap_or(0);
+                        format!("{:width$}  {:>5} opcodes", w, opcodes, width = width)
+                    })
+                    .collect();
+                let noun = if words.len() == 1 { "word" } else { "words" };
+                lines.push(format!("{} {}", words.len(), noun));
+                for line in lines {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Words are listed in alphabetical order. A pattern is a glob, where * \
+             matches anything and ? matches one character, unless it is between \
+             slashes when it is a regular expression. Names are case sensitive.",
+        )
+        .with_parameter("pattern", "Only list the words matching it")
+        .with_examples(&["words", "words *dup*", "words /^[A-Z]/"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "see",
+            "word",
+            "Show the Forth a word was defined with",
+            |_command_id, params, ctx| {
+                let word = match params {
+                    [word] => *word,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("see".to_owned()))),
+                };
+                let lines = match ctx.fc.word_definitions.get(word) {
+                    Some(definition) => pretty_definition(word, definition),
+                    None => return Err(ForthInteractiveError::UnknownWord(word.to_owned())),
+                };
+                for line in lines {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help("Control structures are indented so the shape of the word stands out.")
+        .with_parameter("word", "A word from the dictionary")
+        .with_examples(&["see quad"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "xref",
+            "word",
+            "Show which words call a word, and which words it calls",
+            |_command_id, params, ctx| {
+                let word = match params {
+                    [word] => *word,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("xref".to_owned()))),
+                };
+                let address = match ctx.fc.word_addresses.get(word) {
+                    Some(address) => *address,
+                    None => return Err(ForthInteractiveError::UnknownWord(word.to_owned())),
+                };
+
+                let list = |words: Vec<String>| {
+                    if words.is_empty() {
+                        "nothing".to_owned()
+                    } else {
+                        words.join(" ")
+                    }
+                };
+                let callers = list(callers_of(&ctx.fc, address));
+                let callees = list(callees(&ctx.fc, word));
+                ctx.println(format!("{} is called by: {}", word, callers));
+                ctx.println(format!("{} calls: {}", word, callees));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Calls are worked out from the compiled opcodes, so a word that was \
+             compiled before its callee was redefined still calls the old \
+             definition and isn't listed as a caller of the new one.",
+        )
+        .with_parameter("word", "A word from the dictionary")
+        .with_examples(&["xref dbl"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "history",
+            "word",
+            "Show the earlier definitions of a word that has been redefined",
+            |_command_id, params, ctx| {
+                let word = match params {
+                    [word] => *word,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("history".to_owned()))),
+                };
+                let current = match WordVersion::of(&ctx.fc, word) {
+                    Some(current) => current,
+                    None => return Err(ForthInteractiveError::UnknownWord(word.to_owned())),
+                };
+
+                let describe = |v: &WordVersion| match v.definition.as_ref() {
+                    Some(definition) => {
+                        format!("@{}  {}", v.address, definition_source(word, definition))
+                    }
+                    None => format!("@{}", v.address),
+                };
+                let mut lines: Vec<String> = ctx
+                    .word_history
+                    .versions(word)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| format!("{:>8}: {}", i + 1, describe(v)))
+                    .collect();
+                lines.push(format!("{:>8}: {}", "current", describe(&current)));
+                for line in lines {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Definitions are numbered from the oldest, the numbers are the ones revert \
+             takes. Each is shown with the address its opcodes are compiled at.",
+        )
+        .with_parameter("word", "A word from the dictionary")
+        .with_examples(&["history Eric"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "revert",
+            "word [n]",
+            "Make an earlier definition of a word the current one again",
+            |_command_id, params, ctx| {
+                let (word, n) = match params {
+                    [word] => (*word, ctx.word_history.versions(word).len()),
+                    [word, n] => (*word, n.parse::<usize>()?),
+                    _ => return Ok(CommandHandled::ShowHelp(Some("revert".to_owned()))),
+                };
+                let current = match WordVersion::of(&ctx.fc, word) {
+                    Some(current) => current,
+                    None => return Err(ForthInteractiveError::UnknownWord(word.to_owned())),
+                };
+                let version = match ctx.word_history.take(word, n) {
+                    Some(version) => version,
+                    None => {
+                        ctx.println(format!("{} has no earlier definition {}", word, n));
+                        return Ok(CommandHandled::Handled);
+                    }
+                };
+
+                version.install(&mut ctx.fc, word);
+                ctx.word_history.record(word, current);
+                ctx.println(format!(
+                    "{} is back to its definition at @{}",
+                    word, version.address
+                ));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "The earlier opcodes are still compiled, so the dictionary is just pointed \
+             back at them and words compiled since keep calling whatever they were \
+             compiled against. The definition being replaced goes into the history, \
+             so a revert can be reverted.",
+        )
+        .with_parameter("word", "A word from the dictionary")
+        .with_parameter(
+            "n",
+            "Which earlier definition, as numbered by history, the most recent if not given",
+        )
+        .with_examples(&["revert Eric", "revert Eric 1"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "forget",
+            "word",
+            "Remove a word and everything defined after it",
+            |_command_id, params, ctx| {
+                let word = match params {
+                    [word] => *word,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("forget".to_owned()))),
+                };
+                let boundary = match ctx.fc.word_addresses.get(word) {
+                    Some(address) => *address,
+                    None => return Err(ForthInteractiveError::UnknownWord(word.to_owned())),
+                };
+
+                let forgotten = ctx.forget_from(boundary)?;
+                ctx.println(format!("Forgot {}", forgotten.join(" ")));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Everything compiled from the word's address on is thrown away, along \
+             with any markers set since. A word that was redefined after the \
+             forgotten word goes back to its earlier definition rather than \
+             disappearing.",
+        )
+        .with_parameter("word", "A word from the dictionary")
+        .with_examples(&["forget Eric"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "marker",
+            "[name]",
+            "Set a checkpoint to roll the dictionary back to, or list them",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => {
+                        if ctx.markers.is_empty() {
+                            ctx.println("No markers");
+                        }
+                        let lines: Vec<String> = ctx
+                            .markers
+                            .iter()
+                            .map(|m| {
+                                format!("{}  ({} words)", m.name, m.snapshot.word_addresses.len())
+                            })
+                            .collect();
+                        for line in lines {
+                            ctx.println(line);
+                        }
+                    }
+                    [name] => {
+                        ctx.set_marker(name);
+                        ctx.println(format!("Marker {} set", name));
+                    }
+                    _ => return Ok(CommandHandled::ShowHelp(Some("marker".to_owned()))),
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Roll back to a marker with rollback, which puts every word back the \
+             way it was when the marker was set.",
+        )
+        .with_parameter("name", "What to call the checkpoint")
+        .with_examples(&["marker experiment", "marker"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "rollback",
+            "name",
+            "Put the dictionary back the way it was when a marker was set",
+            |_command_id, params, ctx| {
+                let name = match params {
+                    [name] => *name,
+                    _ => return Ok(CommandHandled::ShowHelp(Some("rollback".to_owned()))),
+                };
+                ctx.rollback(name)?;
+                ctx.println(format!("Rolled back to marker {}", name));
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Words defined since the marker are removed and words redefined since \
+             get their old definitions back. The number stack is left alone. The \
+             marker and any set after it are used up.",
+        )
+        .with_parameter("name", "A marker set with the marker command")
+        .with_examples(&["rollback experiment"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "undo",
+            "No parameters",
+            "Go back to the state before the last line that changed anything",
+            |_command_id, _params, ctx| {
+                if ctx.undo()? {
+                    ctx.println(format!(
+                        "Undone, {} more can be undone",
+                        ctx.undo.undo_len()
+                    ));
+                } else {
+                    ctx.println("Nothing to undo");
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "A snapshot of the dictionary, the compiled opcodes and the number \
+             stack is taken before every line of Forth and every command, and kept \
+             if the line changed any of them. The last 50 are kept. Anything being \
+             debugged is abandoned.",
+        )
+        .not_undoable(),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "redo",
+            "No parameters",
+            "Put back what the last undo took away",
+            |_command_id, _params, ctx| {
+                if ctx.redo()? {
+                    ctx.println(format!(
+                        "Redone, {} more can be redone",
+                        ctx.undo.redo_len()
+                    ));
+                } else {
+                    ctx.println("Nothing to redo");
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help("Anything undone can be redone until a line changes the session again.")
+        .not_undoable(),
+    ));
+
+    command_handlers.push(Box::from(CommandHandler::new(
+        "list_compiled_opcodes",
+        "No parameters",
+        "Show the opcodes that are compiled into memory",
+        |_command_id, _params, ctx| {
+            ctx.println(format!("Compiled Opcodes {:?}", ctx.fc.sm.st.opcodes));
+            //println!("Last compiled Opcode {:?}", fc.last_function);
+            Ok(CommandHandled::Handled)
+        },
+    )));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "disasm",
+            "[word|start..end]",
+            "Show the compiled opcodes one per line, with their addresses",
+            |_command_id, params, ctx| {
+                let opcodes = &ctx.fc.sm.st.opcodes;
+                let range = match params {
+                    [] => 0..opcodes.len(),
+                    [w] if ctx.fc.word_addresses.contains_key(*w) => {
+                        let start = ctx.fc.word_addresses[*w];
+                        start..word_end(opcodes, start)
+                    }
+                    [range] if range.chars().all(|c| c.is_ascii_digit() || c == '.') => {
+                        parse_address_range(range, opcodes.len())?
+                    }
+                    [w] => {
+                        ctx.println(format!("Unable to find Word [{}] in dictionary.", w));
+                        return Ok(CommandHandled::Handled);
+                    }
+                    _ => return Ok(CommandHandled::ShowHelp(Some("disasm".to_owned()))),
+                };
+
+                for line in disassemble(opcodes, &ctx.fc.word_addresses, range) {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "Each word is headed by its name and its last opcode is marked, a CALL \
+             is annotated with the word it calls. Opcodes after the last word are \
+             what the most recent line of Forth compiled to.",
+        )
+        .with_parameter("word", "Show just the opcodes of this word")
+        .with_parameter(
+            "start..end",
+            "Show the opcodes from start up to but not including end, either can be left out",
+        )
+        .with_examples(&["disasm", "disasm quad", "disasm 10..20", "disasm 42"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "debug",
+            "[forth text]",
+            "Start running Forth text an opcode at a time, or show where it has got to",
+            |_command_id, params, ctx| {
+                if !params.is_empty() {
+                    ctx.debugger = Some(Debugger::start(
+                        &mut ctx.fc,
+                        &ctx.word_history,
+                        &params.join(" "),
+                        ctx.gas_limit,
+                    )?);
+                }
+                let lines = match ctx.debugger.as_ref() {
+                    Some(debugger) => debugger.describe(),
+                    None => return Err(ForthInteractiveError::NotDebugging),
+                };
+                for line in lines {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "The text is compiled but nothing runs until step, next, continue or \
+             finish is used, after each of them the next opcode, the number stack \
+             and the return and loop stacks are shown. Once the text is done the \
+             number stack is kept, like any other line of Forth. The session gas \
+             limit applies to the whole run.",
+        )
+        .with_parameter(
+            "forth text",
+            "The Forth to run, it can use any word but can't define new ones",
+        )
+        .with_examples(&["debug 3 quad", "debug"]),
+    ));
+
+    let debugger_commands: [(&str, &[&str], &str, RunUntil); 4] = [
+        ("step", &["s"], "Run the next opcode", RunUntil::Step),
+        (
+            "next",
+            &[],
+            "Run the next opcode, running the whole word if it is a CALL",
+            RunUntil::Next,
+        ),
+        (
+            "continue",
+            &["c"],
+            "Run the debugged text to the end",
+            RunUntil::Continue,
+        ),
+        (
+            "finish",
+            &[],
+            "Run until the word being debugged returns",
+            RunUntil::Finish,
+        ),
+    ];
+    for (id, aliases, help, until) in debugger_commands.iter().copied() {
+        command_handlers.push(Box::from(
+            CommandHandler::new(
+                id,
+                "No parameters",
+                help,
+                move |_command_id, _params, ctx| run_debugger(ctx, until),
+            )
+            .with_aliases(aliases)
+            .with_long_help("Only works while debugging, start with the debug command."),
+        ));
+    }
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "break",
+            "[word|@address] [if TOS=n]",
+            "Stop the debugger when it reaches a word or an opcode address",
+            |_command_id, params, ctx| {
+                if params.is_empty() {
+                    list_breakpoints(ctx);
+                    return Ok(CommandHandled::Handled);
+                }
+
+                let id = ctx.breakpoints.last().map(|b| b.id + 1).unwrap_or(1);
+                let breakpoint = Breakpoint::parse(id, params)?;
+                if let BreakLocation::Word(word) = &breakpoint.location {
+                    if !ctx.fc.word_addresses.contains_key(word) {
+                        return Err(ForthInteractiveError::UnknownWord(word.clone()));
+                    }
+                }
+                ctx.println(format!("Breakpoint {}", breakpoint));
+                ctx.breakpoints.push(breakpoint);
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "While any breakpoints are set, lines of Forth that don't define words \
+             are run by the debugger, and stop when they reach a breakpoint. Carry \
+             on from there with step, next, finish or continue. A breakpoint on a \
+             word follows it if the word is redefined. With a condition the \
+             breakpoint only stops when the top of the number stack passes it, \
+             the comparisons are = != < <= > and >=. Files loaded with l run as \
+             normal and don't stop at breakpoints.",
+        )
+        .with_parameter("word", "Stop when the word is called")
+        .with_parameter("@address", "Stop before the opcode at this address runs")
+        .with_parameter(
+            "if TOS=n",
+            "Only stop when the top of the number stack is n",
+        )
+        .with_examples(&["break quad", "break @12", "break Eric if TOS=3", "break"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "delete",
+            "[n1 n2 ...]",
+            "Delete breakpoints, all of them if none are given",
+            |_command_id, params, ctx| {
+                if params.is_empty() {
+                    ctx.breakpoints.clear();
+                    ctx.println("Deleted all breakpoints");
+                }
+                for n in params {
+                    let id = n.parse::<usize>()?;
+                    match ctx.breakpoints.iter().position(|b| b.id == id) {
+                        Some(i) => {
+                            ctx.breakpoints.remove(i);
+                            ctx.println(format!("Deleted breakpoint {}", id));
+                        }
+                        None => return Err(ForthInteractiveError::NoSuchBreakpoint(id)),
+                    }
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_parameter("n1", "The number break gave the breakpoint")
+        .with_examples(&["delete 2", "delete"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "info",
+            "breakpoints",
+            "Show information about the session",
+            |_command_id, params, ctx| match params {
+                ["breakpoints"] => {
+                    list_breakpoints(ctx);
+                    Ok(CommandHandled::Handled)
+                }
+                _ => Ok(CommandHandled::ShowHelp(Some("info".to_owned()))),
+            },
+        )
+        .with_parameter("breakpoints", "List the breakpoints")
+        .with_examples(&["info breakpoints"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "trace",
+            "[on [file]|off]",
+            "Log every opcode run, with its address, its word and the number stack",
+            |_command_id, params, ctx| {
+                match params {
+                    [] => (),
+                    ["on"] => ctx.trace = Some(Trace::to_output()),
+                    ["on", path] => ctx.trace = Some(Trace::to_file(path)?),
+                    ["off"] => {
+                        if let Some(mut trace) = ctx.trace.take() {
+                            trace.flush()?;
+                        }
+                    }
+                    _ => return Ok(CommandHandled::ShowHelp(Some("trace".to_owned()))),
+                }
+                let status = match ctx.trace.as_ref() {
+                    Some(trace) => format!("Tracing to {}", trace.destination()),
+                    None => "Tracing is off".to_owned(),
+                };
+                ctx.println(status);
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "While tracing is on, Forth that doesn't define words is run by the \
+             debugger's stack machine and each opcode is logged before it runs. \
+             Tracing to stdout prints the log with the rest of the line's output once \
+             the line is done, and still prints it when the line fails, so the lines \
+             leading up to an error or running out of gas aren't lost. With unlimited \
+             gas a loop that never ends never prints its log, trace to a file to \
+             follow it as it runs. A file is replaced when tracing to it starts.",
+        )
+        .with_parameter("on", "Start tracing, to stdout unless a file is given")
+        .with_parameter("file", "Where to write the trace")
+        .with_parameter("off", "Stop tracing")
+        .with_examples(&["trace on", "trace on loop.trace", "trace off", "trace"]),
+    ));
+
+    command_handlers.push(Box::from(
+        CommandHandler::new(
+            "profile",
+            "[--sort column] [forth text]",
+            "Run Forth text and show how many opcodes each word ran",
+            |_command_id, params, ctx| {
+                let (sort, text) = match params {
+                    ["--sort", column, text @ ..] => match ProfileColumn::parse(column) {
+                        Some(sort) => (sort, text),
+                        None => {
+                            ctx.println(format!(
+                                "Unknown column [{}], expected one of: {}",
+                                column,
+                                ProfileColumn::NAMES.join(", ")
+                            ));
+                            return Ok(CommandHandled::Handled);
+                        }
+                    },
+                    text => (ProfileColumn::Inclusive, text),
+                };
+
+                if !text.is_empty() {
+                    let mut debugger = Debugger::start(
+                        &mut ctx.fc,
+                        &ctx.word_history,
+                        &text.join(" "),
+                        ctx.gas_limit,
+                    )?;
+                    let mut profile = Profile::default();
+                    let traps = &mut ctx.fc.sm.trap_handlers;
+                    match debugger.run(RunUntil::Continue, &[], traps, Some(&mut profile)) {
+                        Ok(_) => debugger.write_stacks(&mut ctx.fc),
+                        // Where the gas went is the interesting part when it runs out
+                        Err(ForthInteractiveError::RanOutOfGas { gas_used }) => {
+                            ctx.println(format!(
+                                "Ran out of gas after using {}, this is the profile up to there",
+                                gas_used
+                            ))
+                        }
+                        Err(err) => return Err(err),
+                    }
+                    ctx.profile = Some(profile);
+                }
+
+                let lines = match ctx.profile.as_ref() {
+                    Some(profile) => profile.report(sort),
+                    None => vec!["Nothing has been profiled yet".to_owned()],
+                };
+                for line in lines {
+                    ctx.println(line);
+                }
+                Ok(CommandHandled::Handled)
+            },
+        )
+        .with_long_help(
+            "The text is run by the debugger's stack machine with the session gas \
+             limit, and every opcode is put down to the word it belongs to. The \
+             inclusive count includes the words a word called, the exclusive count \
+             and the share of the gas only what it ran itself. The text being \
+             profiled shows up as <debug>. Without any text the last profile is \
+             shown again, sorted by the column given.",
+        )
+        .with_parameter(
+            "--sort column",
+            "Sort by word, calls, inclusive, exclusive or gas, inclusive if not given",
+        )
+        .with_parameter(
+            "forth text",
+            "The Forth to run, it can use any word but can't define new ones",
+        )
+        .with_examples(&[
+            "profile 10 quad",
+            "profile --sort calls",
+            "profile --sort exclusive 5 fib",
+        ]),
+    ));
+
+    command_handlers.push(Box::from(CommandHandler::new(
+        "clear_number_stack",
+        "No parameters",
+        "Remove all numbers from number stack",
+        |_command_id, _params, ctx| {
+            ctx.fc.sm.st.number_stack.truncate(0);
+            Ok(CommandHandled::Handled)
+        },
+    )));
+
+    command_handlers
+}
+
+fn list_breakpoints(ctx: &mut ReplContext) {
+    if ctx.breakpoints.is_empty() {
+        ctx.println("No breakpoints");
+    }
+    let lines: Vec<String> = ctx.breakpoints.iter().map(|b| b.to_string()).collect();
+    for line in lines {
+        ctx.println(line);
+    }
+}
+
+/// Run the debugger, saying so once the text is done
+fn run_debugger(
+    ctx: &mut ReplContext,
+    until: RunUntil,
+) -> Result<CommandHandled, ForthInteractiveError> {
+    if let Stopped::Finished { gas_used } = ctx.run_debugger(until)? {
+        ctx.println(format!("Finished after {} opcodes", gas_used));
+    }
+    Ok(CommandHandled::Handled)
+}
+
+/// Parse `start..end`, `start..`, `..end` or a single address
+fn parse_address_range(text: &str, len: usize) -> Result<Range<usize>, ForthInteractiveError> {
+    let bound = |b: &str, default: usize| match b {
+        "" => Ok(default),
+        b => b.parse::<usize>(),
+    };
+    match text.split_once("..") {
+        Some((start, end)) => Ok(bound(start, 0)?..bound(end, len)?),
+        None => {
+            let address = text.parse::<usize>()?;
+            Ok(address..address + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_ranges() {
+        for (text, range) in [
+            ("3..7", 3..7),
+            ("3..", 3..20),
+            ("..7", 0..7),
+            ("..", 0..20),
+            ("5", 5..6),
+        ] {
+            assert_eq!(parse_address_range(text, 20).unwrap(), range, "{}", text);
+        }
+        for text in ["", "3...7", "..x", "-1"] {
+            assert!(parse_address_range(text, 20).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::error::ForthInteractiveError;
+use crate::history::WordHistory;
+use crate::machine::{Machine, Step};
+use crate::opcode::{disassemble, Opcode, WordMap};
+use crate::snapshot::Snapshot;
+use crate::source::defines_words;
+use rust_forth_compiler::{ForthCompiler, GasLimit};
+use rust_simple_stack_processor::HandleTrap;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The word the text being debugged is compiled into, the compiler is rolled
+/// straight back to before it
+const DEBUG_WORD: &str = "__debug";
+
+/// What the debugged text is called when showing where the machine is
+const DEBUG_LABEL: &str = "<debug>";
+
+/// How far the debugger runs before stopping again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunUntil {
+    /// Run one opcode
+    Step,
+    /// Run one opcode, and the whole word if it is a CALL
+    Next,
+    /// Run until the text is done
+    Continue,
+    /// Run until the current word returns
+    Finish,
+}
+
+/// Why the debugger stopped running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stopped {
+    /// It ran as far as it was asked to
+    Paused,
+    /// It reached the breakpoint with this id
+    Breakpoint(usize),
+    Finished {
+        gas_used: u64,
+    },
+}
+
+/// Where a breakpoint is
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakLocation {
+    /// The start of a word, followed if the word is redefined
+    Word(String),
+    /// An opcode address
+    Address(usize),
+}
+
+/// A test of the top of the number stack, a breakpoint with one only stops
+/// when it passes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    comparison: &'static str,
+    value: i64,
+}
+
+const COMPARISONS: [&str; 8] = ["==", "!=", "<>", "<=", ">=", "=", "<", ">"];
+
+impl Condition {
+    /// Parse `TOS=3`, `TOS >= -1` and the like
+    pub fn parse(text: &str) -> Result<Condition, ForthInteractiveError> {
+        let invalid = || {
+            ForthInteractiveError::InvalidBreakpoint(format!(
+                "`{}` isn't a condition, expected something like TOS=3",
+                text
+            ))
+        };
+
+        let text: String = text.split_whitespace().collect();
+        let rest = match text.get(..3) {
+            Some(tos) if tos.eq_ignore_ascii_case("TOS") => &text[3..],
+            _ => return Err(invalid()),
+        };
+        let comparison = COMPARISONS
+            .iter()
+            .find(|c| rest.starts_with(*c))
+            .ok_or_else(invalid)?;
+        let value = rest[comparison.len()..]
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+
+        Ok(Condition { comparison, value })
+    }
+
+    /// Whether the condition holds, never with an empty stack
+    pub fn test(&self, number_stack: &[i64]) -> bool {
+        let tos = match number_stack.last() {
+            Some(tos) => *tos,
+            None => return false,
+        };
+        match self.comparison {
+            "==" | "=" => tos == self.value,
+            "!=" | "<>" => tos != self.value,
+            "<=" => tos <= self.value,
+            ">=" => tos >= self.value,
+            "<" => tos < self.value,
+            _ => tos > self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TOS{}{}", self.comparison, self.value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub location: BreakLocation,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    /// Parse the parameters of the break command, `word` or `@address`
+    /// optionally followed by `if` and a condition
+    pub fn parse(id: usize, params: &[&str]) -> Result<Breakpoint, ForthInteractiveError> {
+        let (location, condition) = match params {
+            [location] => (location, None),
+            [location, keyword, condition @ ..]
+                if keyword.eq_ignore_ascii_case("if") && !condition.is_empty() =>
+            {
+                (location, Some(Condition::parse(&condition.join(" "))?))
+            }
+            _ => {
+                return Err(ForthInteractiveError::InvalidBreakpoint(
+                    "expected a word or @address, optionally followed by if and a condition"
+                        .to_owned(),
+                ));
+            }
+        };
+
+        let location = match location.strip_prefix('@') {
+            Some(address) => BreakLocation::Address(address.parse::<usize>()?),
+            None => BreakLocation::Word((*location).to_owned()),
+        };
+        Ok(Breakpoint {
+            id,
+            location,
+            condition,
+        })
+    }
+
+    fn address(&self, word_addresses: &HashMap<String, usize>) -> Option<usize> {
+        match &self.location {
+            BreakLocation::Word(word) => word_addresses.get(word).copied(),
+            BreakLocation::Address(address) => Some(*address),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            BreakLocation::Word(word) => write!(f, "{}: {}", self.id, word)?,
+            BreakLocation::Address(address) => write!(f, "{}: @{}", self.id, address)?,
+        }
+        match &self.condition {
+            Some(condition) => write!(f, " if {}", condition),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Something that wants to see every opcode the debugger runs
+pub trait Observer {
+    /// Called before each opcode runs, `words` says which word each address is in
+    fn observe(&mut self, machine: &Machine, words: &WordMap) -> Result<(), ForthInteractiveError>;
+}
+
+/// Forth text being run an opcode at a time
+pub struct Debugger {
+    machine: Machine,
+    /// The dictionary, with the debugged text in it
+    word_addresses: HashMap<String, usize>,
+    words: WordMap,
+    /// Where the last run stopped, so carrying on doesn't stop at the same
+    /// breakpoint again
+    stopped_at: Option<usize>,
+}
+
+impl Debugger {
+    /// Compile Forth text without running it, ready to run its first opcode
+    pub fn start(
+        fc: &mut ForthCompiler,
+        history: &WordHistory,
+        text: &str,
+        gas_limit: Option<u64>,
+    ) -> Result<Debugger, ForthInteractiveError> {
+        if !Debugger::can_debug(text) {
+            return Err(ForthInteractiveError::CantDebug(
+                "definitions can't be debugged, define the word first and debug text that uses it"
+                    .to_owned(),
+            ));
+        }
+
+        // Compiling the text as a word moves where the compiler puts the next
+        // one, so once its opcodes are copied out the compiler goes back to
+        // how it was
+        let before = Snapshot::capture_keeping(fc, history.addresses());
+        let compiled =
+            fc.execute_string(&format!(": {} {} ;", DEBUG_WORD, text), GasLimit::Unlimited);
+        let start = fc.word_addresses.get(DEBUG_WORD).copied();
+        let opcodes = fc.sm.st.opcodes.clone();
+        before.restore_into(fc)?;
+        compiled?;
+        let start = start.expect("the debugged text was just compiled");
+
+        let mut word_addresses = fc.word_addresses.clone();
+        word_addresses.insert(DEBUG_LABEL.to_owned(), start);
+        let mut machine = Machine::new(opcodes, start, fc.sm.st.number_stack.clone(), gas_limit);
+        machine.scratch_stack = fc.sm.st.scratch_stack.clone();
+
+        Ok(Debugger {
+            words: WordMap::new(&machine.opcodes, &word_addresses),
+            word_addresses,
+            machine,
+            stopped_at: None,
+        })
+    }
+
+    /// Only text that doesn't define words can be run by the debugger
+    pub fn can_debug(text: &str) -> bool {
+        !defines_words(text)
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.machine.is_finished()
+    }
+
+    /// Leave the machine's stacks in the compiler, the way running the text
+    /// there would have
+    pub fn write_stacks(&self, fc: &mut ForthCompiler) {
+        fc.sm.st.number_stack = self.machine.number_stack.clone();
+        fc.sm.st.scratch_stack = self.machine.scratch_stack.clone();
+    }
+
+    /// Run until there is a reason to stop, a breakpoint stops any kind of run.
+    /// TRAPs go to the compiler's trap handlers.
+    pub fn run(
+        &mut self,
+        until: RunUntil,
+        breakpoints: &[Breakpoint],
+        trap_handlers: &mut [Box<dyn HandleTrap>],
+        mut observer: Option<&mut dyn Observer>,
+    ) -> Result<Stopped, ForthInteractiveError> {
+        let depth = self.machine.depth();
+        let over_call = matches!(self.machine.current(), Some(Opcode::CALL));
+        let breakpoints: Vec<(usize, &Breakpoint)> = breakpoints
+            .iter()
+            .filter_map(|b| b.address(&self.word_addresses).map(|a| (a, b)))
+            .collect();
+
+        // Breakpoints are checked before each opcode runs, except the one
+        // carried on from
+        let mut resuming = self.stopped_at.take() == Some(self.machine.pc);
+        let mut stepped = false;
+        loop {
+            let hit = breakpoints.iter().find(|(address, b)| {
+                *address == self.machine.pc
+                    && b.condition
+                        .map(|c| c.test(&self.machine.number_stack))
+                        .unwrap_or(true)
+            });
+            if let (Some((_, b)), false) = (hit, resuming) {
+                self.stopped_at = Some(self.machine.pc);
+                return Ok(Stopped::Breakpoint(b.id));
+            }
+            resuming = false;
+
+            let stop = match until {
+                RunUntil::Step => true,
+                RunUntil::Next => !over_call || self.machine.depth() <= depth,
+                RunUntil::Continue => false,
+                RunUntil::Finish => self.machine.depth() < depth,
+            };
+            if stepped && stop {
+                self.stopped_at = Some(self.machine.pc);
+                return Ok(Stopped::Paused);
+            }
+
+            if let Some(observer) = observer.as_mut() {
+                observer.observe(&self.machine, &self.words)?;
+            }
+            if self.machine.step(trap_handlers)? == Step::Finished {
+                return Ok(Stopped::Finished {
+                    gas_used: self.machine.gas_used,
+                });
+            }
+            stepped = true;
+        }
+    }
+
+    /// The opcode about to run and the state of the stacks
+    pub fn describe(&self) -> Vec<String> {
+        let m = &self.machine;
+        let mut lines = Vec::new();
+
+        if !m.is_finished() {
+            let op = disassemble(&m.opcodes, &self.word_addresses, m.pc..m.pc + 1)
+                .pop()
+                .unwrap_or_default();
+            match self.words.word_at(m.pc) {
+                Some(word) => lines.push(format!("{}  [in {}]", op, word)),
+                None => lines.push(op),
+            }
+        }
+
+        let return_stack: Vec<String> = m
+            .return_stack
+            .iter()
+            .map(|a| match self.words.word_at(*a) {
+                Some(word) => format!("{} in {}", a, word),
+                None => a.to_string(),
+            })
+            .collect();
+        lines.push(format!("Number stack {:?}", m.number_stack));
+        lines.push(format!("Return stack [{}]", return_stack.join(", ")));
+        lines.push(format!("Loop stack {:?}", m.loop_stack));
+        if !m.scratch_stack.is_empty() {
+            lines.push(format!(">R stack {:?}", m.scratch_stack));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::compiler;
+
+    #[test]
+    fn start_leaves_the_compiler_alone() {
+        let mut fc = compiler(": dbl DUP ADD ; 3");
+        let before = Snapshot::capture(&fc);
+
+        let mut debugger =
+            Debugger::start(&mut fc, &WordHistory::default(), "dbl 1 dbl", None).unwrap();
+        assert!(before.matches(&fc));
+        assert!(!fc.word_addresses.contains_key(DEBUG_WORD));
+
+        // The next word goes where it would have without the debugger
+        fc.execute_string(": next 1 ;", GasLimit::Unlimited)
+            .unwrap();
+        assert_eq!(fc.word_addresses["next"], before.opcodes.len());
+
+        let traps = &mut fc.sm.trap_handlers;
+        debugger.run(RunUntil::Continue, &[], traps, None).unwrap();
+        debugger.write_stacks(&mut fc);
+        assert_eq!(fc.sm.st.number_stack, vec![6, 2]);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_opcode() {
+        let mut fc = compiler(": dbl DUP ADD ;");
+        let mut debugger =
+            Debugger::start(&mut fc, &WordHistory::default(), "1 dbl dbl", None).unwrap();
+        let start = debugger.machine().pc;
+        let breakpoints = [
+            Breakpoint::parse(1, &[&format!("@{}", start)]).unwrap(),
+            Breakpoint::parse(2, &["dbl", "if", "TOS=2"]).unwrap(),
+        ];
+
+        let traps = &mut fc.sm.trap_handlers;
+        let mut run = |debugger: &mut Debugger| {
+            debugger
+                .run(RunUntil::Continue, &breakpoints, traps, None)
+                .unwrap()
+        };
+        assert_eq!(run(&mut debugger), Stopped::Breakpoint(1));
+        assert_eq!(debugger.machine().pc, start);
+        assert_eq!(run(&mut debugger), Stopped::Breakpoint(2));
+        assert_eq!(debugger.machine().number_stack, vec![2]);
+        assert!(matches!(run(&mut debugger), Stopped::Finished { .. }));
+        assert_eq!(debugger.machine().number_stack, vec![4]);
+    }
+}
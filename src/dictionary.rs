@@ -0,0 +1,205 @@
+use crate::error::ForthInteractiveError;
+use crate::export::definition_source;
+use crate::opcode::called_addresses;
+use crate::source::{definition_text, tokenize};
+use regex::Regex;
+use rust_forth_compiler::ForthCompiler;
+
+/// Turn a `words` pattern into a regex. `/.../` is a regex, anything else is a
+/// glob where `*` matches any run of characters and `?` any one character.
+pub fn word_pattern(pattern: &str) -> Result<Regex, ForthInteractiveError> {
+    let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+        Some(regex) => regex.to_owned(),
+        None => {
+            let glob: Vec<String> = pattern
+                .chars()
+                .map(|c| match c {
+                    '*' => ".*".to_owned(),
+                    '?' => ".".to_owned(),
+                    c => regex::escape(&c.to_string()),
+                })
+                .collect();
+            format!("^{}$", glob.concat())
+        }
+    };
+
+    Regex::new(&regex).map_err(|err| ForthInteractiveError::InvalidPattern {
+        pattern: pattern.to_owned(),
+        message: err.to_string(),
+    })
+}
+
+/// The names compiled at an address, the opcodes of a word that was redefined
+/// have none
+fn names_at(fc: &ForthCompiler, address: usize) -> Vec<String> {
+    let mut names: Vec<String> = fc
+        .word_addresses
+        .iter()
+        .filter(|(_, a)| **a == address)
+        .map(|(w, _)| w.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// The words a word calls, by what its compiled opcodes call rather than the
+/// names in its definition, so a call to a word that has since been redefined
+/// shows up as the old address.
+pub fn callees(fc: &ForthCompiler, word: &str) -> Vec<String> {
+    let opcodes = match fc.word_opcodes.get(word) {
+        Some(opcodes) => opcodes,
+        None => return Vec::new(),
+    };
+
+    let mut callees: Vec<String> = called_addresses(opcodes)
+        .into_iter()
+        .flat_map(|address| match names_at(fc, address) {
+            names if names.is_empty() => vec![format!("@{} (redefined)", address)],
+            names => names,
+        })
+        .collect();
+    callees.sort();
+    callees.dedup();
+    callees
+}
+
+/// The words whose compiled opcodes call the code at an address
+pub fn callers_of(fc: &ForthCompiler, address: usize) -> Vec<String> {
+    let mut callers: Vec<String> = fc
+        .word_opcodes
+        .iter()
+        .filter(|(_, opcodes)| called_addresses(opcodes).contains(&address))
+        .map(|(w, _)| w.clone())
+        .collect();
+    callers.sort();
+    callers
+}
+
+/// A word's definition laid out with each control structure indented, short
+/// definitions without any stay as they were written
+pub fn pretty_definition(name: &str, definition: &str) -> Vec<String> {
+    let text = definition_text(definition);
+    let tokens = tokenize(&text);
+    let structured = tokens.iter().any(|t| {
+        matches!(
+            t.text,
+            "IF" | "ELSE"
+                | "THEN"
+                | "BEGIN"
+                | "WHILE"
+                | "REPEAT"
+                | "AGAIN"
+                | "UNTIL"
+                | "DO"
+                | "LOOP"
+                | "+LOOP"
+        )
+    });
+    if !structured {
+        return definition_source(name, definition)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect();
+    }
+
+    let mut lines = vec![format!(": {}", name)];
+    let mut depth = 1;
+    let mut line: Vec<&str> = Vec::new();
+    for token in tokens {
+        match token.text {
+            "IF" | "BEGIN" | "DO" => {
+                line.push(token.text);
+                end_line(&mut line, depth, &mut lines);
+                depth += 1;
+            }
+            "ELSE" | "WHILE" => {
+                if token.text == "WHILE" {
+                    line.push(token.text);
+                }
+                end_line(&mut line, depth, &mut lines);
+                if token.text == "ELSE" {
+                    lines.push(format!("{}ELSE", "    ".repeat(depth - 1)));
+                }
+            }
+            "THEN" | "AGAIN" | "UNTIL" | "REPEAT" | "LOOP" | "+LOOP" => {
+                end_line(&mut line, depth, &mut lines);
+                depth = (depth - 1).max(1);
+                line.push(token.text);
+            }
+            text => line.push(text),
+        }
+    }
+    end_line(&mut line, depth, &mut lines);
+    lines.push(";".to_owned());
+    lines
+}
+
+fn end_line(line: &mut Vec<&str>, depth: usize, lines: &mut Vec<String>) {
+    if !line.is_empty() {
+        lines.push(format!("{}{}", "    ".repeat(depth), line.join(" ")));
+        line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::compiler;
+    use rust_forth_compiler::GasLimit;
+
+    #[test]
+    fn pretty_definition_is_forth() {
+        let fc = compiler(": Eric 1 2 ; : pos DUP IF DROP 1 ELSE 0 THEN ;");
+        assert_eq!(
+            pretty_definition("Eric", &fc.word_definitions["Eric"]),
+            vec![": Eric 1 2 ;"]
+        );
+        assert_eq!(
+            pretty_definition("pos", &fc.word_definitions["pos"]),
+            vec![
+                ": pos",
+                "    DUP IF",
+                "        DROP 1",
+                "    ELSE",
+                "        0",
+                "    THEN",
+                ";"
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_are_globs_or_regexes() {
+        for (pattern, matching, other) in [
+            ("Eric", "Eric", "Erica"),
+            ("Er*", "Eric", "Tamara"),
+            ("?ric", "Eric", "Ric"),
+            ("a.b", "a.b", "axb"),
+            ("/^pre.*[12]$/", "predefined2", "predefined3"),
+            ("/mar/", "Tamara", "Eric"),
+        ] {
+            let regex = word_pattern(pattern).unwrap();
+            assert!(regex.is_match(matching), "{} {}", pattern, matching);
+            assert!(!regex.is_match(other), "{} {}", pattern, other);
+        }
+        assert!(matches!(
+            word_pattern("/(/"),
+            Err(ForthInteractiveError::InvalidPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn callers_follow_the_compiled_calls() {
+        let mut fc = compiler(": dbl DUP ADD ; : quad dbl dbl ; : six dbl 3 MUL ;");
+        let dbl = fc.word_addresses["dbl"];
+        assert_eq!(callers_of(&fc, dbl), vec!["quad", "six"]);
+        assert_eq!(callees(&fc, "quad"), vec!["dbl"]);
+
+        // The callers keep calling the old definition
+        fc.execute_string(": dbl 2 MUL ;", GasLimit::Unlimited)
+            .unwrap();
+        assert_eq!(callers_of(&fc, dbl), vec!["quad", "six"]);
+        assert!(callers_of(&fc, fc.word_addresses["dbl"]).is_empty());
+        assert_eq!(callees(&fc, "quad"), vec![format!("@{} (redefined)", dbl)]);
+    }
+}
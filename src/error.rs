@@ -0,0 +1,247 @@
+use rust_forth_compiler::ForthError;
+use std::error::Error;
+use std::fmt;
+
+/// Where in a Forth source file something went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column in characters, starting at 1
+    pub column: usize,
+    /// The word being compiled or run when it went wrong
+    pub token: String,
+    /// The text of the whole line, for showing the token in context
+    pub source_line: String,
+}
+
+impl SourceLocation {
+    /// Locate a token that starts at byte `offset` of `source`
+    pub fn new(file: &str, source: &str, offset: usize, token: &str) -> SourceLocation {
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or_else(|| source.len());
+
+        SourceLocation {
+            file: file.to_owned(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            token: token.to_owned(),
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keep any tabs so the caret lines up however wide the terminal shows them
+        let padding: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(self.token.chars().count().max(1));
+
+        writeln!(
+            f,
+            "{}:{}:{}: at `{}`",
+            self.file, self.line, self.column, self.token
+        )?;
+        writeln!(f, "    {}", self.source_line)?;
+        write!(f, "    {}{}", padding, carets)
+    }
+}
+
+/// This Enum lists the errors that the Forth Interpreter might return
+#[derive(Debug)]
+pub enum ForthInteractiveError {
+    ForthError(ForthError),
+    IOError(std::io::Error),
+    ParseIntError(std::num::ParseIntError),
+    RanOutOfGas {
+        gas_used: u64,
+    },
+    InvalidGasLimit(String),
+    UnknownCommand {
+        name: String,
+        /// A registered command the name is a likely typo of
+        suggestion: Option<String>,
+    },
+    AmbiguousCommand {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+    /// A command id or alias was registered twice
+    DuplicateCommand(String),
+    CouldNotReadFile {
+        path: String,
+        error: std::io::Error,
+    },
+    CouldNotWriteFile {
+        path: String,
+        error: std::io::Error,
+    },
+    /// An error while loading a source file, and where in the file it happened
+    InSource {
+        location: SourceLocation,
+        error: Box<ForthInteractiveError>,
+    },
+    /// A snapshot file written by a different version of the format
+    SnapshotVersion {
+        found: String,
+        expected: u32,
+    },
+    InvalidSnapshot {
+        line: usize,
+        message: String,
+    },
+    /// The compiler didn't end up in the state a snapshot describes
+    SnapshotMismatch(String),
+    /// The debugger's stack machine couldn't run the opcode at pc
+    StackMachine {
+        pc: usize,
+        message: String,
+    },
+    CantDebug(String),
+    /// A debugger command was given while nothing is being debugged
+    NotDebugging,
+    InvalidBreakpoint(String),
+    NoSuchBreakpoint(usize),
+    UnknownWord(String),
+    UnknownMarker(String),
+    InvalidPattern {
+        pattern: String,
+        message: String,
+    },
+    /// A file named by INCLUDE or REQUIRE isn't in any of the places looked
+    IncludeNotFound {
+        file: String,
+        searched: Vec<String>,
+    },
+    /// The files that include each other, ending with the one included again
+    IncludeCycle(Vec<String>),
+}
+
+impl fmt::Display for ForthInteractiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForthInteractiveError::ForthError(err) => write!(f, "Forth error: {:?}", err),
+            ForthInteractiveError::IOError(err) => write!(f, "I/O error: {}", err),
+            ForthInteractiveError::ParseIntError(err) => write!(f, "Not a number: {}", err),
+            ForthInteractiveError::RanOutOfGas { gas_used } => write!(
+                f,
+                "Ran out of gas after using {}, raise the limit with the gas command",
+                gas_used
+            ),
+            ForthInteractiveError::InvalidGasLimit(limit) => write!(
+                f,
+                "Invalid gas limit `{}`, expected a number or unlimited",
+                limit
+            ),
+            ForthInteractiveError::UnknownCommand { name, suggestion } => match suggestion {
+                Some(suggestion) => write!(
+                    f,
+                    "Unknown command `{}`, did you mean `{}`?",
+                    name, suggestion
+                ),
+                None => write!(f, "Unknown command `{}`", name),
+            },
+            ForthInteractiveError::AmbiguousCommand { prefix, candidates } => {
+                write!(f, "`{}` could be any of: {}", prefix, candidates.join(", "))
+            }
+            ForthInteractiveError::DuplicateCommand(name) => {
+                write!(f, "A command called `{}` is already registered", name)
+            }
+            ForthInteractiveError::CouldNotReadFile { path, error } => {
+                write!(f, "Couldn't read {}: {}", path, error)
+            }
+            ForthInteractiveError::CouldNotWriteFile { path, error } => {
+                write!(f, "Couldn't write {}: {}", path, error)
+            }
+            ForthInteractiveError::InSource { location, error } => {
+                write!(f, "{}\n{}", error, location)
+            }
+            ForthInteractiveError::SnapshotVersion { found, expected } => write!(
+                f,
+                "Snapshot is format version {}, this build can only restore version {}",
+                found, expected
+            ),
+            ForthInteractiveError::InvalidSnapshot { line, message } => {
+                write!(f, "Invalid snapshot at line {}: {}", line, message)
+            }
+            ForthInteractiveError::SnapshotMismatch(message) => {
+                write!(f, "Couldn't restore snapshot: {}", message)
+            }
+            ForthInteractiveError::StackMachine { pc, message } => {
+                write!(f, "Stack machine error at {}: {}", pc, message)
+            }
+            ForthInteractiveError::CantDebug(message) => write!(f, "Can't debug: {}", message),
+            ForthInteractiveError::NotDebugging => {
+                write!(f, "Nothing is being debugged, start with the debug command")
+            }
+            ForthInteractiveError::InvalidBreakpoint(message) => {
+                write!(f, "Invalid breakpoint: {}", message)
+            }
+            ForthInteractiveError::NoSuchBreakpoint(id) => write!(f, "No breakpoint {}", id),
+            ForthInteractiveError::UnknownWord(word) => {
+                write!(f, "Unable to find Word [{}] in dictionary.", word)
+            }
+            ForthInteractiveError::UnknownMarker(name) => write!(f, "No marker called `{}`", name),
+            ForthInteractiveError::InvalidPattern { pattern, message } => {
+                write!(f, "Invalid pattern `{}`: {}", pattern, message)
+            }
+            ForthInteractiveError::IncludeNotFound { file, searched } => write!(
+                f,
+                "Couldn't find {} to include, looked for {}",
+                file,
+                searched.join(", ")
+            ),
+            ForthInteractiveError::IncludeCycle(files) => {
+                write!(f, "Include cycle: {}", files.join(" -> "))
+            }
+        }
+    }
+}
+
+impl Error for ForthInteractiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ForthInteractiveError::IOError(err) => Some(err),
+            ForthInteractiveError::ParseIntError(err) => Some(err),
+            ForthInteractiveError::CouldNotReadFile { error, .. } => Some(error),
+            ForthInteractiveError::CouldNotWriteFile { error, .. } => Some(error),
+            ForthInteractiveError::InSource { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Convert std::num::ParseIntError to a ForthInteractiveError so our functions can
+/// return a single Error type.
+impl From<std::num::ParseIntError> for ForthInteractiveError {
+    fn from(err: std::num::ParseIntError) -> ForthInteractiveError {
+        ForthInteractiveError::ParseIntError(err)
+    }
+}
+
+/// Convert ForthError to a ForthInteractiveError so our functions can
+/// return a single Error type.
+impl From<ForthError> for ForthInteractiveError {
+    fn from(err: ForthError) -> ForthInteractiveError {
+        ForthInteractiveError::ForthError(err)
+    }
+}
+
+/// Convert std::io::Error to a ForthInteractiveError so our functions can
+/// return a single Error type.
+impl From<std::io::Error> for ForthInteractiveError {
+    fn from(err: std::io::Error) -> ForthInteractiveError {
+        ForthInteractiveError::IOError(err)
+    }
+}
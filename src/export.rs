@@ -0,0 +1,288 @@
+use crate::history::WordHistory;
+use crate::opcode::called_addresses;
+use crate::source::{definition_text, tokenize};
+use rust_forth_compiler::ForthCompiler;
+use std::collections::{HashMap, HashSet};
+
+/// The words a definition calls, other than itself
+pub fn dependencies(name: &str, definition: &str, fc: &ForthCompiler) -> Vec<String> {
+    let text = definition_text(definition);
+    let mut words: Vec<String> = tokenize(&text)
+        .into_iter()
+        .map(|t| t.text)
+        .filter(|w| *w != name && fc.word_addresses.contains_key(*w))
+        .map(|w| w.to_owned())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
+
+/// Forth source that rebuilds every word in the dictionary when loaded
+#[derive(Debug, Default)]
+pub struct Export {
+    /// Each definition's source, in the order to load them
+    pub definitions: Vec<String>,
+    /// Anything the source can't rebuild the way it is now
+    pub warnings: Vec<String>,
+}
+
+impl Export {
+    /// Every word with a definition, written so each definition comes after the
+    /// definitions it calls.
+    ///
+    /// Words are otherwise kept in the order they were compiled in. A word that
+    /// was redefined after something used it is written once for each
+    /// definition still called, using the earlier ones from the history, so
+    /// every word calls the same definitions once the source is loaded.
+    pub fn new(fc: &ForthCompiler, history: &WordHistory) -> Export {
+        let mut exporter = Exporter {
+            versions: HashMap::new(),
+            bound: HashMap::new(),
+            visiting: HashSet::new(),
+            export: Export::default(),
+        };
+
+        let current = fc.word_definitions.iter().filter_map(|(w, definition)| {
+            let address = *fc.word_addresses.get(w)?;
+            Some((w.as_str(), address, definition, fc.word_opcodes.get(w)))
+        });
+        let earlier = history.iter().filter_map(|(w, v)| {
+            let definition = v.definition.as_ref()?;
+            Some((w, v.address, definition, v.opcodes.as_ref()))
+        });
+        for (name, address, definition, opcodes) in current.chain(earlier) {
+            let uses = dependencies(name, definition, fc)
+                .iter()
+                .map(|w| fc.word_addresses[w])
+                .collect();
+            let calls = opcodes.map(|o| called_addresses(o)).unwrap_or_default();
+            exporter.versions.insert(
+                address,
+                Version {
+                    name,
+                    definition,
+                    uses,
+                    calls,
+                },
+            );
+        }
+
+        let mut forgotten: Vec<&str> = exporter
+            .versions
+            .values()
+            .filter(|v| v.calls.iter().any(|c| !exporter.versions.contains_key(c)))
+            .map(|v| v.name)
+            .collect();
+        forgotten.sort_unstable();
+        forgotten.dedup();
+        for w in forgotten {
+            exporter.export.warnings.push(format!(
+                "{} calls a definition that isn't in the history any more, \
+                 the exported {} calls the current one",
+                w, w
+            ));
+        }
+
+        let mut words: Vec<(usize, &str)> = fc
+            .word_definitions
+            .keys()
+            .filter_map(|w| Some((*fc.word_addresses.get(w)?, w.as_str())))
+            .collect();
+        words.sort_unstable();
+        for (address, _) in words.iter() {
+            exporter.write(*address);
+        }
+
+        // Writing an earlier definition for a word compiled later leaves that
+        // definition current, so the words it replaced are written again
+        for _ in 0..=words.len() {
+            let stale: Vec<usize> = words
+                .iter()
+                .filter(|(address, w)| exporter.bound.get(w) != Some(address))
+                .map(|(address, _)| *address)
+                .collect();
+            if stale.is_empty() {
+                break;
+            }
+            for address in stale {
+                exporter.write(address);
+            }
+        }
+        exporter.export
+    }
+
+    pub fn source(&self) -> String {
+        let mut source = String::new();
+        for d in self.definitions.iter() {
+            source.push_str(d);
+            source.push('\n');
+        }
+        source
+    }
+}
+
+/// A definition of a word, the current one or an earlier one
+struct Version<'f> {
+    name: &'f str,
+    definition: &'f str,
+    /// The current definitions of the words it names, which have to exist for
+    /// it to compile
+    uses: Vec<usize>,
+    /// The addresses of the definitions it was compiled to call
+    calls: Vec<usize>,
+}
+
+struct Exporter<'f> {
+    /// Every definition that can be written, by address
+    versions: HashMap<usize, Version<'f>>,
+    /// Which definition each word has at this point in the source
+    bound: HashMap<&'f str, usize>,
+    visiting: HashSet<usize>,
+    export: Export,
+}
+
+impl<'f> Exporter<'f> {
+    /// Write the definition at an address, after whatever it calls
+    fn write(&mut self, address: usize) {
+        let (name, definition, uses, calls) = match self.versions.get(&address) {
+            Some(v) => (v.name, v.definition, v.uses.clone(), v.calls.clone()),
+            None => return,
+        };
+        if self.bound.get(name) == Some(&address) || !self.visiting.insert(address) {
+            return;
+        }
+
+        // The words it calls a forgotten definition of only have to exist for
+        // it to compile
+        let called: HashSet<&str> = calls
+            .iter()
+            .filter_map(|c| self.versions.get(c).map(|v| v.name))
+            .collect();
+        for u in uses {
+            let undefined = self
+                .versions
+                .get(&u)
+                .map(|v| !called.contains(v.name) && !self.bound.contains_key(v.name))
+                .unwrap_or(false);
+            if undefined {
+                self.write(u);
+            }
+        }
+        // Writing one definition it calls can replace another it calls, so go
+        // round until it would call all of them
+        for _ in 0..=calls.len() {
+            let missing: Vec<usize> = calls
+                .iter()
+                .copied()
+                .filter(|c| *c != address)
+                .filter(|c| match self.versions.get(c) {
+                    Some(v) => self.bound.get(v.name) != Some(c),
+                    None => false,
+                })
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+            for c in missing {
+                self.write(c);
+            }
+        }
+
+        self.visiting.remove(&address);
+        self.export
+            .definitions
+            .push(definition_source(name, definition));
+        self.bound.insert(name, address);
+    }
+}
+
+/// The Forth source that defines a word
+pub fn definition_source(name: &str, definition: &str) -> String {
+    let text = definition_text(definition);
+    // A definition ending in a `\` comment already ends with a newline
+    let separator = if text.ends_with('\n') { "" } else { " " };
+    format!(": {} {}{};", name, text, separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::WordVersion;
+    use crate::test_util::compiler;
+    use rust_forth_compiler::GasLimit;
+
+    #[test]
+    fn definition_source_is_forth() {
+        let fc = compiler(": Tamara 1 2 ; : Eric Tamara 3 ;");
+        assert_eq!(
+            definition_source("Eric", &fc.word_definitions["Eric"]),
+            ": Eric Tamara 3 ;"
+        );
+    }
+
+    #[test]
+    fn earlier_definitions_come_before_their_callers() {
+        let mut fc = compiler(": b 1 ;");
+        let mut history = WordHistory::default();
+        history.record("b", WordVersion::of(&fc, "b").unwrap());
+        fc.execute_string(": a b DUP ; : b 2 ; : c a b ;", GasLimit::Unlimited)
+            .unwrap();
+        assert_eq!(
+            dependencies("c", &fc.word_definitions["c"], &fc),
+            vec!["a", "b"]
+        );
+
+        let export = Export::new(&fc, &history);
+        assert_eq!(
+            export.definitions,
+            vec![": b 1 ;", ": a b DUP ;", ": b 2 ;", ": c a b ;"]
+        );
+        assert!(export.warnings.is_empty());
+        let mut reloaded = compiler(&export.source());
+        reloaded.execute_string("c", GasLimit::Unlimited).unwrap();
+        assert_eq!(reloaded.sm.st.number_stack, vec![1, 1, 2]);
+
+        // Without the earlier b, a has to use the current one
+        let export = Export::new(&fc, &WordHistory::default());
+        assert_eq!(
+            export.definitions,
+            vec![": b 2 ;", ": a b DUP ;", ": c a b ;"]
+        );
+        assert_eq!(export.warnings.len(), 1);
+    }
+
+    #[test]
+    fn reverted_words_are_written_last() {
+        let mut fc = compiler(": x 1 ;");
+        let mut history = WordHistory::default();
+        let first = WordVersion::of(&fc, "x").unwrap();
+        fc.execute_string(": x 2 ; : y x ;", GasLimit::Unlimited)
+            .unwrap();
+        history.record("x", WordVersion::of(&fc, "x").unwrap());
+        first.install(&mut fc, "x");
+
+        let mut reloaded = compiler(&Export::new(&fc, &history).source());
+        reloaded.execute_string("x y", GasLimit::Unlimited).unwrap();
+        assert_eq!(reloaded.sm.st.number_stack, vec![1, 2]);
+    }
+
+    #[test]
+    fn export_rebuilds_the_dictionary() {
+        let mut fc = compiler(
+            ": I 1 ;\n: Love 2 ;\n: Tamara I Love ;\n: Son 3 ;\n: Eric Tamara Son ;\n\
+             : remarks 1 ( one ) 2 \\ two\n ;\n: sum 0 5 0 DO I ADD LOOP ;\n\
+             : pos DUP IF DROP 1 THEN ;",
+        );
+
+        let mut reloaded = compiler(&Export::new(&fc, &WordHistory::default()).source());
+        assert_eq!(reloaded.word_definitions, fc.word_definitions);
+        assert_eq!(reloaded.word_opcodes, fc.word_opcodes);
+
+        for c in [&mut fc, &mut reloaded] {
+            c.execute_string("Eric remarks sum 7 pos", GasLimit::Unlimited)
+                .unwrap();
+        }
+        assert_eq!(reloaded.sm.st.number_stack, fc.sm.st.number_stack);
+    }
+}
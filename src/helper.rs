@@ -0,0 +1,193 @@
+use crate::session::DispatchMode;
+use crate::source::{classify, nesting, Nesting, TokenKind};
+use crate::vocabulary::{BUILTIN_WORDS, CONTROL_FLOW_WORDS};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+const RESET: &str = "\x1b[0m";
+
+/// ANSI color used to show each kind of token
+fn token_color(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Number => "\x1b[33m",
+        TokenKind::Definition => "\x1b[1;35m",
+        TokenKind::DefinedName => "\x1b[1;34m",
+        TokenKind::ControlFlow => "\x1b[1;36m",
+        TokenKind::Builtin => "\x1b[32m",
+        TokenKind::UserWord => "\x1b[34m",
+        TokenKind::Unknown => "\x1b[1;31m",
+    }
+}
+
+/// Line editor helper for the REPL prompts, completes command names, dictionary
+/// words and file names, highlights Forth text as it is typed, and keeps asking
+/// for more lines while a definition or control structure is left open.
+pub struct ReplHelper {
+    /// None when the prompt only takes Forth text, like `i>`
+    dispatch_mode: Option<DispatchMode>,
+    command_names: Vec<String>,
+    /// Commands whose parameters are file names
+    file_commands: Vec<String>,
+    words: Vec<String>,
+    file_completer: FilenameCompleter,
+}
+
+impl ReplHelper {
+    pub fn new(
+        dispatch_mode: DispatchMode,
+        command_names: Vec<String>,
+        file_commands: Vec<String>,
+        words: Vec<String>,
+    ) -> ReplHelper {
+        ReplHelper {
+            dispatch_mode: Some(dispatch_mode),
+            command_names,
+            file_commands,
+            words,
+            file_completer: FilenameCompleter::new(),
+        }
+    }
+
+    /// A helper for a prompt where every line is Forth text
+    pub fn forth_only(words: Vec<String>) -> ReplHelper {
+        ReplHelper {
+            dispatch_mode: None,
+            command_names: Vec::new(),
+            file_commands: Vec::new(),
+            words,
+            file_completer: FilenameCompleter::new(),
+        }
+    }
+
+    /// The line with any command prefix removed, if it is a REPL command
+    fn command_text<'l>(&self, line: &'l str) -> Option<&'l str> {
+        self.dispatch_mode?.command_text(line)
+    }
+
+    /// The command being typed if this is the first word of the line, prefix removed
+    fn partial_command<'l>(&self, partial: &'l str) -> Option<&'l str> {
+        match self.dispatch_mode? {
+            DispatchMode::CommandsOnly => Some(partial),
+            DispatchMode::ForthByDefault { command_prefix } => partial.strip_prefix(command_prefix),
+        }
+    }
+
+    fn complete_command(&self, partial: &str) -> Vec<Pair> {
+        let mut candidates: Vec<Pair> = self
+            .command_names
+            .iter()
+            .filter(|id| id.starts_with(partial))
+            .map(|id| Pair {
+                display: id.clone(),
+                replacement: self
+                    .dispatch_mode
+                    .map_or_else(|| id.clone(), |m| m.display_command(id)),
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates
+    }
+
+    fn complete_word(&self, partial: &str) -> Vec<Pair> {
+        let partial = partial.to_ascii_uppercase();
+        let mut matches: Vec<&str> = self
+            .words
+            .iter()
+            .map(|w| w.as_str())
+            .chain(BUILTIN_WORDS.iter().copied())
+            .chain(CONTROL_FLOW_WORDS.iter().copied())
+            .filter(|w| w.to_ascii_uppercase().starts_with(&partial))
+            .collect();
+        matches.sort_unstable();
+        matches.dedup();
+        matches
+            .into_iter()
+            .map(|w| Pair {
+                display: w.to_owned(),
+                replacement: w.to_owned(),
+            })
+            .collect()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let partial = &line[start..pos];
+
+        if line[..start].trim().is_empty() {
+            if let Some(command) = self.partial_command(partial) {
+                return Ok((start, self.complete_command(command)));
+            }
+        } else if let Some(command_text) = self.command_text(line) {
+            let command_id = command_text.split_whitespace().next().unwrap_or_default();
+            if self.file_commands.iter().any(|c| c == command_id) {
+                return self.file_completer.complete(line, pos, ctx);
+            }
+        }
+
+        Ok((start, self.complete_word(partial)))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if self.command_text(line).is_some() {
+            return Cow::Borrowed(line);
+        }
+
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for (token, kind) in classify(line, |w| self.words.iter().any(|k| k == w)) {
+            highlighted.push_str(&line[last..token.start]);
+            highlighted.push_str(token_color(kind));
+            highlighted.push_str(token.text);
+            highlighted.push_str(RESET);
+            last = token.end();
+        }
+        highlighted.push_str(&line[last..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // Every keystroke can change what a word is, so always redraw
+        true
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if self.command_text(input).is_some() {
+            return Ok(ValidationResult::Valid(None));
+        }
+
+        Ok(match nesting(input) {
+            Nesting::Complete => ValidationResult::Valid(None),
+            Nesting::Open(_) => ValidationResult::Incomplete,
+            Nesting::Invalid(message) => {
+                ValidationResult::Invalid(Some(format!("  <- {}", message)))
+            }
+        })
+    }
+}
+
+impl Helper for ReplHelper {}
@@ -0,0 +1,144 @@
+use crate::opcode::Opcode;
+use crate::snapshot::Snapshot;
+use crate::watch::LoadedFile;
+use rust_forth_compiler::ForthCompiler;
+use std::collections::HashMap;
+
+/// One definition of a word, enough to make it the current one again
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordVersion {
+    pub address: usize,
+    pub definition: Option<String>,
+    pub opcodes: Option<Vec<Opcode>>,
+}
+
+impl WordVersion {
+    /// The current definition of a word
+    pub fn of(fc: &ForthCompiler, word: &str) -> Option<WordVersion> {
+        Some(WordVersion {
+            address: *fc.word_addresses.get(word)?,
+            definition: fc.word_definitions.get(word).cloned(),
+            opcodes: fc.word_opcodes.get(word).cloned(),
+        })
+    }
+
+    /// The current definition of every word
+    pub fn all(fc: &ForthCompiler) -> HashMap<String, WordVersion> {
+        fc.word_addresses
+            .keys()
+            .filter_map(|w| WordVersion::of(fc, w).map(|v| (w.clone(), v)))
+            .collect()
+    }
+
+    /// Make this the word's definition again. Its opcodes are still compiled
+    /// at its address, so this just points the dictionary back at them.
+    pub fn install(&self, fc: &mut ForthCompiler, word: &str) {
+        fc.word_addresses.insert(word.to_owned(), self.address);
+        match &self.definition {
+            Some(definition) => fc
+                .word_definitions
+                .insert(word.to_owned(), definition.clone()),
+            None => fc.word_definitions.remove(word),
+        };
+        match &self.opcodes {
+            Some(opcodes) => fc.word_opcodes.insert(word.to_owned(), opcodes.clone()),
+            None => fc.word_opcodes.remove(word),
+        };
+    }
+}
+
+/// The earlier definitions of every word that has been redefined
+#[derive(Debug, Clone, Default)]
+pub struct WordHistory {
+    /// Oldest first
+    versions: HashMap<String, Vec<WordVersion>>,
+}
+
+impl WordHistory {
+    /// Earlier definitions of a word, oldest first
+    pub fn versions(&self, word: &str) -> &[WordVersion] {
+        self.versions.get(word).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn record(&mut self, word: &str, version: WordVersion) {
+        self.versions
+            .entry(word.to_owned())
+            .or_default()
+            .push(version);
+    }
+
+    /// Take an earlier definition out of the history, `n` counts from 1
+    pub fn take(&mut self, word: &str, n: usize) -> Option<WordVersion> {
+        let versions = self.versions.get_mut(word)?;
+        if n == 0 || n > versions.len() {
+            return None;
+        }
+        Some(versions.remove(n - 1))
+    }
+
+    /// Every earlier definition of every word
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &WordVersion)> {
+        self.versions
+            .iter()
+            .flat_map(|(w, versions)| versions.iter().map(move |v| (w.as_str(), v)))
+    }
+
+    /// Where every earlier definition is compiled
+    pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.iter().map(|(_, v)| v.address)
+    }
+
+    /// Drop the definitions compiled at or after an address, for when the
+    /// opcodes from there on are thrown away
+    pub fn retain_below(&mut self, boundary: usize) {
+        for versions in self.versions.values_mut() {
+            versions.retain(|v| v.address < boundary);
+        }
+        self.versions.retain(|_, versions| !versions.is_empty());
+    }
+
+    /// Forget every earlier definition, for when the compiler is replaced
+    pub fn clear(&mut self) {
+        self.versions.clear();
+    }
+}
+
+/// A checkpoint the dictionary can be rolled back to
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub name: String,
+    pub snapshot: Snapshot,
+    pub history: WordHistory,
+    /// The files that had been loaded, so REQUIRE loads any loaded since again
+    pub loaded_files: Vec<LoadedFile>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::definition_source;
+    use crate::test_util::compiler;
+    use rust_forth_compiler::GasLimit;
+
+    #[test]
+    fn earlier_versions_read_as_forth() {
+        let mut fc = compiler(": Eric 1 2 ;");
+        let mut history = WordHistory::default();
+        history.record("Eric", WordVersion::of(&fc, "Eric").unwrap());
+        fc.execute_string(": Eric 3 ( three ) ;", GasLimit::Unlimited)
+            .unwrap();
+
+        let shown: Vec<String> = history
+            .versions("Eric")
+            .iter()
+            .chain(WordVersion::of(&fc, "Eric").iter())
+            .map(|v| definition_source("Eric", v.definition.as_ref().unwrap()))
+            .collect();
+        assert_eq!(shown, vec![": Eric 1 2 ;", ": Eric 3 ( three ) ;"]);
+
+        let earlier = history.take("Eric", 1).unwrap();
+        earlier.install(&mut fc, "Eric");
+        fc.execute_string("Eric", GasLimit::Unlimited).unwrap();
+        assert_eq!(fc.sm.st.number_stack, vec![1, 2]);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::ForthInteractiveError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Find a file named by `INCLUDE` or `REQUIRE`, next to the file including it
+/// first and then in each directory of the include path in turn
+pub fn resolve_include(
+    including_file: &Path,
+    name: &str,
+    include_path: &[PathBuf],
+) -> Result<PathBuf, ForthInteractiveError> {
+    let here = including_file.parent().unwrap_or_else(|| Path::new(""));
+    let candidates: Vec<PathBuf> = std::iter::once(here.join(name))
+        .chain(include_path.iter().map(|dir| dir.join(name)))
+        .collect();
+
+    match candidates.iter().find(|p| p.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ForthInteractiveError::IncludeNotFound {
+            file: name.to_owned(),
+            searched: candidates.iter().map(|p| p.display().to_string()).collect(),
+        }),
+    }
+}
+
+/// Whether two paths name the same file, however they were written
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,52 @@
+//! An interactive front end for rust-forth-compiler.
+//!
+//! The REPL itself lives in ReplSession, so other tools can embed it and feed it
+//! lines of input, the binary in this crate is a thin wrapper around it.
+
+extern crate regex;
+extern crate rust_simple_stack_processor;
+extern crate rustyline;
+
+mod command;
+mod commands;
+mod debugger;
+mod dictionary;
+mod error;
+mod export;
+mod helper;
+mod history;
+mod include;
+mod machine;
+mod opcode;
+mod profile;
+mod registry;
+mod session;
+mod snapshot;
+mod source;
+mod stack;
+#[cfg(test)]
+mod test_util;
+mod trace;
+mod undo;
+mod vocabulary;
+mod watch;
+
+pub use command::{CommandHandled, CommandHandler, HandleCommand};
+pub use debugger::{BreakLocation, Breakpoint, Condition, Debugger, Observer, RunUntil, Stopped};
+pub use error::{ForthInteractiveError, SourceLocation};
+pub use helper::ReplHelper;
+pub use history::{Marker, WordHistory, WordVersion};
+pub use include::resolve_include;
+pub use machine::{Machine, Step};
+pub use opcode::{Opcode, WordMap};
+pub use profile::{Profile, ProfileColumn, WordProfile};
+pub use registry::CommandRegistry;
+pub use session::{
+    parse_gas_limit, DispatchMode, Output, ReplContext, ReplSession, DEFAULT_GAS_LIMIT,
+};
+pub use snapshot::{Snapshot, SNAPSHOT_VERSION};
+pub use source::{nesting, Nesting};
+pub use stack::{format_stack, NumberBase};
+pub use trace::Trace;
+pub use undo::{UndoHistory, UndoState, DEFAULT_UNDO_LIMIT};
+pub use watch::{LoadedFile, Watch, WatchedFile, WordChanges};
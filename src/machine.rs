@@ -0,0 +1,393 @@
+use crate::error::ForthInteractiveError;
+use crate::opcode::Opcode;
+use rust_forth_compiler::ForthError;
+use rust_simple_stack_processor::{HandleTrap, StackMachineState, TrapHandled};
+use std::convert::TryFrom;
+use std::mem;
+
+/// What a single step of the machine did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Running,
+    /// The outermost word returned
+    Finished,
+}
+
+/// A stack machine that runs the compiler's opcodes one at a time, so they can
+/// be watched. It behaves the same way as the stack machine inside
+/// ForthCompiler, including charging one unit of gas per opcode and finishing
+/// once a TRAP is handled.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub opcodes: Vec<Opcode>,
+    /// The opcode that runs next
+    pub pc: usize,
+    pub number_stack: Vec<i64>,
+    /// Where each CALL returns to, innermost last
+    pub return_stack: Vec<usize>,
+    /// The stack `>R` and `R>` use
+    pub scratch_stack: Vec<i64>,
+    /// The index and limit of each DO loop, innermost last
+    pub loop_stack: Vec<(i64, i64)>,
+    pub cells: Vec<i64>,
+    pub gas_used: u64,
+    /// None means unlimited
+    pub gas_limit: Option<u64>,
+    finished: bool,
+}
+
+impl Machine {
+    pub fn new(
+        opcodes: Vec<Opcode>,
+        start: usize,
+        number_stack: Vec<i64>,
+        gas_limit: Option<u64>,
+    ) -> Machine {
+        Machine {
+            opcodes,
+            pc: start,
+            number_stack,
+            return_stack: Vec::new(),
+            scratch_stack: Vec::new(),
+            loop_stack: Vec::new(),
+            cells: Vec::new(),
+            gas_used: 0,
+            gas_limit,
+            finished: false,
+        }
+    }
+
+    /// The opcode that runs next, None once the machine has finished
+    pub fn current(&self) -> Option<&Opcode> {
+        if self.finished {
+            None
+        } else {
+            self.opcodes.get(self.pc)
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// How many calls deep the machine is, the outermost word is depth 0
+    pub fn depth(&self) -> usize {
+        self.return_stack.len()
+    }
+
+    /// Run the opcode at pc, a TRAP goes to each of the handlers in turn
+    pub fn step(
+        &mut self,
+        trap_handlers: &mut [Box<dyn HandleTrap>],
+    ) -> Result<Step, ForthInteractiveError> {
+        if self.finished {
+            return Ok(Step::Finished);
+        }
+        let op = match self.opcodes.get(self.pc) {
+            Some(op) => op.clone(),
+            None => return Err(self.error("ran past the end of the opcodes")),
+        };
+
+        let mut next_pc = self.pc + 1;
+        match op {
+            Opcode::JMP => next_pc = self.pop_address()?,
+            Opcode::JR => {
+                let offset = self.pop()?;
+                next_pc = self.offset_pc(offset)?;
+            }
+            Opcode::JRZ | Opcode::JRNZ => {
+                let offset = self.pop()?;
+                let x = self.pop()?;
+                if (x == 0) == matches!(op, Opcode::JRZ) {
+                    next_pc = self.offset_pc(offset)?;
+                }
+            }
+            Opcode::CALL => {
+                next_pc = self.pop_address()?;
+                self.return_stack.push(self.pc + 1);
+            }
+            Opcode::RET => match self.return_stack.pop() {
+                Some(address) => next_pc = address,
+                None => {
+                    self.finished = true;
+                    return Ok(Step::Finished);
+                }
+            },
+            Opcode::CMPZ => {
+                let x = self.pop()?;
+                self.number_stack.push(if x == 0 { -1 } else { 0 });
+            }
+            Opcode::CMPNZ => {
+                let x = self.pop()?;
+                self.number_stack.push(if x == 0 { 0 } else { -1 });
+            }
+            Opcode::LDI(n) => self.number_stack.push(n),
+            Opcode::DROP => {
+                self.pop()?;
+            }
+            Opcode::SWAP => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                self.number_stack.extend(&[x, y]);
+            }
+            Opcode::SWAP2 => {
+                let (x4, x3) = (self.pop()?, self.pop()?);
+                let (x2, x1) = (self.pop()?, self.pop()?);
+                self.number_stack.extend(&[x3, x4, x1, x2]);
+            }
+            Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV | Opcode::AND => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                let result = match op {
+                    Opcode::ADD => y.wrapping_add(x),
+                    Opcode::SUB => x.wrapping_sub(y),
+                    Opcode::MUL => y.wrapping_mul(x),
+                    Opcode::DIV if x == 0 => return Err(self.error("division by zero")),
+                    Opcode::DIV => y.wrapping_div(x),
+                    _ => y & x,
+                };
+                self.number_stack.push(result);
+            }
+            Opcode::NOT => {
+                let x = self.pop()?;
+                self.number_stack.push(if x == 0 { 1 } else { 0 });
+            }
+            Opcode::DUP => {
+                let x = self.pop()?;
+                self.number_stack.extend(&[x, x]);
+            }
+            Opcode::DUP2 => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                self.number_stack.extend(&[y, x, y, x]);
+            }
+            Opcode::OVER2 => {
+                let (x4, x3) = (self.pop()?, self.pop()?);
+                let (x2, x1) = (self.pop()?, self.pop()?);
+                self.number_stack.extend(&[x1, x2, x3, x4, x1, x2]);
+            }
+            Opcode::TRAP => {
+                let trap_id = self.pop()?;
+                return self.trap(trap_id, trap_handlers);
+            }
+            Opcode::NOP => (),
+            Opcode::PUSHLP => {
+                let index = self.pop()?;
+                let limit = self.pop()?;
+                self.loop_stack.push((index, limit));
+            }
+            Opcode::INCLP => self.current_loop()?.0 += 1,
+            Opcode::ADDLP => {
+                let increment = self.pop()?;
+                self.current_loop()?.0 += increment;
+            }
+            Opcode::GETLP => {
+                let index = self.current_loop()?.0;
+                self.number_stack.push(index);
+            }
+            Opcode::GETLP2 => match self.loop_stack.len().checked_sub(2) {
+                Some(i) => self.number_stack.push(self.loop_stack[i].0),
+                None => return Err(self.error("loop stack underflow")),
+            },
+            Opcode::DROPLP => {
+                if self.loop_stack.pop().is_none() {
+                    return Err(self.error("loop stack underflow"));
+                }
+            }
+            Opcode::CMPLOOP => {
+                let (index, limit) = *self.current_loop()?;
+                self.number_stack.push(if index >= limit { 1 } else { 0 });
+            }
+            Opcode::GtR => {
+                let x = self.pop()?;
+                self.scratch_stack.push(x);
+            }
+            Opcode::RGt => {
+                let x = self.pop_scratch()?;
+                self.number_stack.push(x);
+            }
+            Opcode::RAt => {
+                let x = self.pop_scratch()?;
+                self.scratch_stack.push(x);
+                self.number_stack.push(x);
+            }
+            Opcode::GtR2 => {
+                let x = self.pop()?;
+                let y = self.pop()?;
+                self.scratch_stack.extend(&[y, x]);
+            }
+            Opcode::RGt2 | Opcode::RAt2 => {
+                let x = self.pop_scratch()?;
+                let y = self.pop_scratch()?;
+                if matches!(op, Opcode::RAt2) {
+                    self.scratch_stack.extend(&[y, x]);
+                }
+                self.number_stack.extend(&[y, x]);
+            }
+            Opcode::NEWCELLS => {
+                let count = self.pop_cells()?;
+                self.cells.resize(self.cells.len() + count, 0);
+            }
+            Opcode::MOVETOCELLS => {
+                let cells = self.cell_range()?;
+                for cell in cells {
+                    self.cells[cell] = self.pop()?;
+                }
+            }
+            Opcode::MOVEFROMCELLS => {
+                let cells = self.cell_range()?;
+                for cell in cells.rev() {
+                    self.number_stack.push(self.cells[cell]);
+                }
+            }
+        }
+
+        self.pc = next_pc;
+        self.gas_used += 1;
+        match self.gas_limit {
+            Some(limit) if self.gas_used > limit => Err(ForthInteractiveError::RanOutOfGas {
+                gas_used: self.gas_used,
+            }),
+            _ => Ok(Step::Running),
+        }
+    }
+
+    /// Hand a trap to the handlers with the machine's stacks, the first to
+    /// handle it finishes the run
+    fn trap(
+        &mut self,
+        trap_id: i64,
+        trap_handlers: &mut [Box<dyn HandleTrap>],
+    ) -> Result<Step, ForthInteractiveError> {
+        let mut st = StackMachineState::default();
+        mem::swap(&mut st.number_stack, &mut self.number_stack);
+        mem::swap(&mut st.scratch_stack, &mut self.scratch_stack);
+        let mut handled = Err(ForthError::UnhandledTrap.into());
+        for h in trap_handlers.iter_mut() {
+            match h.handle_trap(trap_id, &mut st) {
+                Ok(TrapHandled::NotHandled) => (),
+                Ok(TrapHandled::Handled) => {
+                    handled = Ok(Step::Finished);
+                    break;
+                }
+                Err(err) => {
+                    handled = Err(ForthError::from(err).into());
+                    break;
+                }
+            }
+        }
+        mem::swap(&mut st.number_stack, &mut self.number_stack);
+        mem::swap(&mut st.scratch_stack, &mut self.scratch_stack);
+
+        if handled.is_ok() {
+            self.finished = true;
+        }
+        handled
+    }
+
+    fn error(&self, message: &str) -> ForthInteractiveError {
+        ForthInteractiveError::StackMachine {
+            pc: self.pc,
+            message: message.to_owned(),
+        }
+    }
+
+    fn pop(&mut self) -> Result<i64, ForthInteractiveError> {
+        self.number_stack
+            .pop()
+            .ok_or_else(|| self.error("number stack underflow"))
+    }
+
+    fn pop_address(&mut self) -> Result<usize, ForthInteractiveError> {
+        let x = self.pop()?;
+        usize::try_from(x).map_err(|_| self.error("negative address"))
+    }
+
+    fn pop_cells(&mut self) -> Result<usize, ForthInteractiveError> {
+        let x = self.pop()?;
+        usize::try_from(x).map_err(|_| self.error("invalid cell operation"))
+    }
+
+    /// The cells a move between them and the number stack covers, there has to
+    /// be at least one and they all have to exist
+    fn cell_range(&mut self) -> Result<std::ops::Range<usize>, ForthInteractiveError> {
+        let count = self.pop_cells()?;
+        let address = self.pop_cells()?;
+        let end = address.saturating_add(count);
+        if count < 1 || self.cells.len() < end {
+            return Err(self.error("invalid cell operation"));
+        }
+        Ok(address..address + count)
+    }
+
+    fn pop_scratch(&mut self) -> Result<i64, ForthInteractiveError> {
+        self.scratch_stack
+            .pop()
+            .ok_or_else(|| self.error("scratch stack underflow"))
+    }
+
+    fn current_loop(&mut self) -> Result<&mut (i64, i64), ForthInteractiveError> {
+        let error = self.error("loop stack underflow");
+        self.loop_stack.last_mut().ok_or(error)
+    }
+
+    fn offset_pc(&self, offset: i64) -> Result<usize, ForthInteractiveError> {
+        usize::try_from(self.pc as i64 + offset).map_err(|_| self.error("jump before address 0"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use rust_forth_compiler::{ForthCompiler, GasLimit};
+    use rust_simple_stack_processor::TrapHandler;
+
+    const WORDS: &str = ": dbl DUP ADD ; : sum 0 5 0 DO I ADD LOOP ; \
+                         : count BEGIN DUP WHILE 1- REPEAT ; : pos DUP IF DROP 1 THEN ;";
+
+    fn compiler() -> ForthCompiler {
+        let mut fc = test_util::compiler(WORDS);
+        fc.sm
+            .trap_handlers
+            .push(Box::new(TrapHandler::new(100, |_, st| {
+                st.number_stack.push(42);
+                Ok(TrapHandled::Handled)
+            })));
+        fc
+    }
+
+    /// The number stack after running text on a Machine
+    fn run(text: &str) -> Result<Vec<i64>, ForthInteractiveError> {
+        let mut fc = compiler();
+        fc.execute_string(&format!(": __test {} ;", text), GasLimit::Unlimited)?;
+        let start = fc.word_addresses["__test"];
+        let mut machine = Machine::new(fc.sm.st.opcodes.clone(), start, Vec::new(), None);
+        while machine.step(&mut fc.sm.trap_handlers)? == Step::Running {}
+        Ok(machine.number_stack)
+    }
+
+    #[test]
+    fn runs_like_the_compiler() {
+        for text in [
+            "10 3 SUB 10 3 DIV 6 7 MUL 1 2 SWAP",
+            "1 2 3 4 2SWAP 2OVER 2DUP 2DROP",
+            "5 1+ 1- 2+ 2- 2* 2/ 3 3 = 3 4 <> 6 3 AND NOT",
+            "7 dbl sum 3 count 0 pos -2 pos",
+            "0 3 0 DO 3 0 DO I J MUL ADD LOOP LOOP 0 10 0 DO I ADD 3 +LOOP",
+            "1 2 100 TRAP 3",
+        ] {
+            let mut fc = compiler();
+            fc.execute_string(text, GasLimit::Unlimited).unwrap();
+            assert_eq!(run(text).unwrap(), fc.sm.st.number_stack, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unhandled_traps_are_errors() {
+        assert!(matches!(
+            run("1 2 TRAP"),
+            Err(ForthInteractiveError::ForthError(ForthError::UnhandledTrap))
+        ));
+    }
+}
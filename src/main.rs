@@ -1,210 +1,168 @@
 extern crate rustyline;
 
-use rust_forth_compiler::ForthCompiler;
-use rust_forth_compiler::ForthError;
-use rust_forth_compiler::GasLimit;
+mod cli;
+
+use cli::{Options, StartupAction};
+use rust_forth_interactive_compiler::{
+    nesting, CommandHandled, CommandHandler, ForthInteractiveError, Nesting, Output, ReplHelper,
+    ReplSession,
+};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::fs;
-
-/// This Enum lists the errors that the Forth Interpreter might return
-#[derive(Debug)]
-pub enum ForthInteractiveError {
-    UnknownError,
-    ForthError(ForthError),
-    IOError(std::io::Error),
-    ParseIntError(std::num::ParseIntError),
-}
-
-pub enum CommandHandled {
-    Handled,
-    NotHandled,
-}
-
-// Chain of Command Pattern
-pub trait HandleCommand {
-    fn handle_command(
-        &mut self,
-        command_id: &str,
-        parameters: &[&str],
-        fc: &mut ForthCompiler,
-    ) -> Result<CommandHandled, ForthInteractiveError>;
-    fn command_id(&self) -> String;
-    fn usage_text(&self) -> String;
-    fn help_text(&self) -> String;
-}
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
 
-pub struct CommandHandler<'a> {
-    command_id: String,
-    usage_text: String,
-    help_text: String,
-    to_run: Box<
-        dyn Fn(&str, &[&str], &mut ForthCompiler) -> Result<CommandHandled, ForthInteractiveError>
-            + 'a,
-    >,
-}
+    let mut session = ReplSession::new();
+    if let Some(gas_limit) = options.gas_limit {
+        session.set_gas_limit(gas_limit);
+    }
+    for dir in options.include_path.iter() {
+        session.add_include_path(dir);
+    }
+    if let Some(dispatch_mode) = options.dispatch_mode {
+        session.set_dispatch_mode(dispatch_mode);
+    }
 
-impl<'a> CommandHandler<'a> {
-    pub fn new<C>(command_id: &str, usage_text: &str, help_text: &str, f: C) -> CommandHandler<'a>
-    where
-        C: Fn(&str, &[&str], &mut ForthCompiler) -> Result<CommandHandled, ForthInteractiveError>
-            + 'a,
-    {
-        CommandHandler {
-            command_id: command_id.to_owned(),
-            usage_text: usage_text.to_owned(),
-            help_text: help_text.to_owned(),
-            to_run: Box::new(f),
+    session
+        .add_handler(Box::from(CommandHandler::new(
+            "i",
+            "Enter interactive Forth text",
+            "Enter interactive Forth text",
+            |_command_id, _params, ctx| {
+                let helper = ReplHelper::forth_only(ctx.dictionary_words());
+                ctx.execute(&enter_interactive_text(helper))?;
+                Ok(CommandHandled::Handled)
+            },
+        )))
+        .expect("i is not one of the built in commands");
+
+    if !options.no_init {
+        for path in init_files() {
+            // A broken init file shouldn't stop anyone from getting to the prompt
+            match session.load_file(&path) {
+                Ok(output) => print!("{}", output),
+                Err(err) => eprintln!("Error loading init file {}: {}", path.display(), err),
+            }
         }
     }
-}
 
-impl<'a> HandleCommand for CommandHandler<'a> {
-    fn handle_command(
-        &mut self,
-        command_id: &str,
-        parameters: &[&str],
-        fc: &mut ForthCompiler,
-    ) -> Result<CommandHandled, ForthInteractiveError> {
-        if command_id == self.command_id {
-            return (self.to_run)(self.command_id.as_ref(), parameters, fc);
+    // Without a prompt to come back to, the first error is the end of the run
+    let stop_on_error = !options.interactive();
+
+    for action in options.startup.iter() {
+        let result = match action {
+            StartupAction::Load(file) => session.load_file(file),
+            StartupAction::Eval(text) => session.execute(text),
+        };
+        if !report(&mut session, result) && stop_on_error {
+            process::exit(1);
         }
-        Ok(CommandHandled::NotHandled)
     }
 
-    fn command_id(&self) -> String {
-        self.command_id.clone()
+    if options.batch {
+        run_batch(&mut session);
     }
 
-    fn usage_text(&self) -> String {
-        self.usage_text.clone()
+    if options.interactive() {
+        run_interactive(&mut session);
     }
+}
+
+/// The init files that exist, user level first so the project one can override it
+fn init_files() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
 
-    fn help_text(&self) -> String {
-        self.help_text.clone()
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_dir) = config_dir {
+        candidates.push(config_dir.join("rust-forth").join("init.fth"));
     }
+    candidates.push(PathBuf::from("init.forth"));
+
+    candidates.into_iter().filter(|p| p.is_file()).collect()
 }
 
-/// Convert std::num::ParseIntError to a ForthInteractiveError so our functions can
-/// return a single Error type.
-impl From<std::num::ParseIntError> for ForthInteractiveError {
-    fn from(err: std::num::ParseIntError) -> ForthInteractiveError {
-        ForthInteractiveError::ParseIntError(err)
+/// Print what a command produced, returns false if it failed
+fn report(session: &mut ReplSession, result: Result<Output, ForthInteractiveError>) -> bool {
+    match result {
+        Ok(output) => {
+            print!("{}", output);
+            true
+        }
+        Err(err) => {
+            print!("{}", session.take_failed_output());
+            eprintln!("Error executing command: {}", err);
+            false
+        }
     }
 }
 
-/// Convert std::num::ParseIntError to a ForthInteractiveError so our functions can
-/// return a single Error type.
-impl From<ForthError> for ForthInteractiveError {
-    fn from(err: ForthError) -> ForthInteractiveError {
-        ForthInteractiveError::ForthError(err)
+/// Process stdin a statement at a time, exits the process on the first error.
+/// Like the prompt, Forth lines are gathered until they close every definition
+/// and control structure they open.
+fn run_batch(session: &mut ReplSession) {
+    let mut statement = String::new();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Error reading stdin: {:?}", err);
+                process::exit(1);
+            }
+        };
+        if statement.is_empty() && session.dispatch_mode().command_text(&line).is_some() {
+            run_batch_statement(session, &line);
+            continue;
+        }
+
+        statement.push_str(&line);
+        statement.push('\n');
+        if let Nesting::Open(_) = nesting(&statement) {
+            continue;
+        }
+        run_batch_statement(session, &std::mem::take(&mut statement));
+    }
+    // Left open at the end of the input, the compiler reports what's wrong
+    if !statement.trim().is_empty() {
+        run_batch_statement(session, &statement);
     }
 }
 
-/// Convert std::io::Error to a ForthInteractiveError so our functions can
-/// return a single Error type.
-impl From<std::io::Error> for ForthInteractiveError {
-    fn from(err: std::io::Error) -> ForthInteractiveError {
-        ForthInteractiveError::IOError(err)
+fn run_batch_statement(session: &mut ReplSession, statement: &str) {
+    // A watched file can be saved while a long batch runs
+    let reloaded = session.reload_watched();
+    if !report(session, reloaded) {
+        process::exit(1);
+    }
+    let result = session.process_line(statement);
+    if !report(session, result) {
+        process::exit(1);
     }
 }
 
-fn main() -> Result<(), ForthError> {
+fn run_interactive(session: &mut ReplSession) {
     println!("This is the rust-forth-interactive-compiler");
 
-    let mut fc = ForthCompiler::default();
-
-    let mut command_handlers: Vec<Box<dyn HandleCommand>> = Vec::new();
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "l",
-        "file1.fs [file2.fs]",
-        "Load Forth file",
-        |_command_id, params, fc| {
-            for n in params {
-                let startup = fs::read_to_string(n)?;
-                fc.execute_string(&startup, GasLimit::Limited(100))?;
-            }
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "n",
-        "No Parameters",
-        "Print number stack",
-        |_command_id, _params, fc| {
-            println!("Number Stack {:?}", fc.sm.st.number_stack);
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "p",
-        "n1 [n2]",
-        "Push numbers on stack",
-        |_command_id, params, fc| {
-            for n in params {
-                fc.sm.st.number_stack.push(n.parse::<i64>()?);
-            }
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "i",
-        "Enter interactive Forth text",
-        "Enter interactive Forth text",
-        |_command_id, _params, fc| {
-            fc.execute_string(&enter_interactive_text(), GasLimit::Limited(100))?;
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "list_words",
-        "Enter words you wish to list",
-        "List words that are compiled into memory",
-        |_command_id, params, fc| {
-            for w in params {
-                if let Some(offset) = fc.word_addresses.get(*w) {
-                    println!("Word: {} Location: {}", w, offset);
-                } else {
-                    println!("Unable to find Word [{}] in dictionary.", w);
-                }
-                if let Some(original_forth) = fc.word_definitions.get(*w) {
-                    println!("Word: {} Forth: {}", w, original_forth);
-                }
-                if let Some(opcodes) = fc.word_opcodes.get(*w) {
-                    println!("Word: {} Opcodes: {:?}", w, opcodes);
-                }
-            }
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "list_compiled_opcodes",
-        "No parameters",
-        "Show the opcodes that are compiled into memory",
-        |_command_id, _params, fc| {
-            println!("Compiled Opcodes {:?}", fc.sm.st.opcodes);
-            //println!("Last compiled Opcode {:?}", fc.last_function);
-            Ok(CommandHandled::Handled)
-        },
-    )));
-
-    command_handlers.push(Box::from(CommandHandler::new(
-        "clear_number_stack",
-        "No parameters",
-        "Remove all numbers from number stack",
-        |_command_id, _params, fc| {
-            fc.sm.st.number_stack.truncate(0);
-            Ok(CommandHandled::Handled)
-        },
-    )));
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(session.helper()));
     if rl.load_history("history.txt").is_err() {
         println!("No previous history.");
     }
@@ -213,47 +171,25 @@ fn main() -> Result<(), ForthError> {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                println!("Line: {}", line);
 
-                // Okay, so we have a line, each line starts with a command, and then has optional parameters
-                let words: Vec<&str> = line.split_whitespace().collect();
-                // If nothing to talk about, just ignore...
-                if words.is_empty() {
-                    continue;
-                }
-
-                let command = words[0];
-                let parameters = &words[1..];
-
-                // Try to handle the command here
-                let mut handled = false;
-                for h in command_handlers.iter_mut() {
-                    match h.handle_command(command, parameters, &mut fc) {
-                        Ok(CommandHandled::Handled) => {
-                            handled = true;
-                        }
-                        Ok(CommandHandled::NotHandled) => (),
-                        Err(err) => {
-                            println!();
-                            println!();
-                            println!("Error executing command: {:?}", err);
-                            println!();
-                            println!();
-                        }
+                // Pick up watched files saved while the line was being typed
+                let reloaded = session.reload_watched();
+                report(session, reloaded);
+
+                match session.process_line(&line) {
+                    Ok(output) => print!("{}", output),
+                    Err(err) => {
+                        print!("{}", session.take_failed_output());
+                        println!();
+                        println!();
+                        println!("Error executing command: {}", err);
+                        println!();
+                        println!();
                     }
                 }
 
-                if !handled {
-                    println!("Help text:");
-                    for h in command_handlers.iter() {
-                        println!(
-                            "    Help: {} Command: {} {}",
-                            h.help_text(),
-                            h.command_id(),
-                            h.usage_text()
-                        );
-                    }
-                }
+                // The line may have defined new words
+                rl.set_helper(Some(session.helper()));
             }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -270,15 +206,13 @@ fn main() -> Result<(), ForthError> {
         }
     }
     rl.save_history("history.txt").unwrap();
-
-    Ok(())
 }
 
-fn enter_interactive_text() -> String {
+fn enter_interactive_text(helper: ReplHelper) -> String {
     let mut return_value = String::new();
 
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(helper));
     if rl.load_history("history_forth_interactive.txt").is_err() {
         println!("No previous history.");
     }
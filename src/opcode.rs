@@ -0,0 +1,230 @@
+pub use rust_simple_stack_processor::Opcode;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+
+/// Parse an opcode written the way Debug prints it, `LDI(5)` or `ADD`
+pub fn parse_opcode(text: &str) -> Option<Opcode> {
+    if let Some(n) = text.strip_prefix("LDI(").and_then(|t| t.strip_suffix(')')) {
+        return n.parse::<i64>().ok().map(Opcode::LDI);
+    }
+
+    let opcode = match text {
+        "JMP" => Opcode::JMP,
+        "JR" => Opcode::JR,
+        "JRZ" => Opcode::JRZ,
+        "JRNZ" => Opcode::JRNZ,
+        "CALL" => Opcode::CALL,
+        "CMPZ" => Opcode::CMPZ,
+        "CMPNZ" => Opcode::CMPNZ,
+        "DROP" => Opcode::DROP,
+        "SWAP" => Opcode::SWAP,
+        "SWAP2" => Opcode::SWAP2,
+        "RET" => Opcode::RET,
+        "ADD" => Opcode::ADD,
+        "SUB" => Opcode::SUB,
+        "MUL" => Opcode::MUL,
+        "DIV" => Opcode::DIV,
+        "NOT" => Opcode::NOT,
+        "DUP" => Opcode::DUP,
+        "DUP2" => Opcode::DUP2,
+        "TRAP" => Opcode::TRAP,
+        "NOP" => Opcode::NOP,
+        "PUSHLP" => Opcode::PUSHLP,
+        "INCLP" => Opcode::INCLP,
+        "ADDLP" => Opcode::ADDLP,
+        "GETLP" => Opcode::GETLP,
+        "GETLP2" => Opcode::GETLP2,
+        "DROPLP" => Opcode::DROPLP,
+        "CMPLOOP" => Opcode::CMPLOOP,
+        "OVER2" => Opcode::OVER2,
+        "GtR" => Opcode::GtR,
+        "RGt" => Opcode::RGt,
+        "RAt" => Opcode::RAt,
+        "GtR2" => Opcode::GtR2,
+        "RGt2" => Opcode::RGt2,
+        "RAt2" => Opcode::RAt2,
+        "AND" => Opcode::AND,
+        "NEWCELLS" => Opcode::NEWCELLS,
+        "MOVETOCELLS" => Opcode::MOVETOCELLS,
+        "MOVEFROMCELLS" => Opcode::MOVEFROMCELLS,
+        _ => return None,
+    };
+    Some(opcode)
+}
+
+/// Where the compiled words end and any code left over from the last execution
+/// starts.
+pub fn compiled_length(opcodes: &[Opcode], word_addresses: impl Iterator<Item = usize>) -> usize {
+    match word_addresses.max() {
+        Some(last_word) => word_end(opcodes, last_word),
+        None => 0,
+    }
+}
+
+/// One past the end of the word that starts at `start`, every word ends with
+/// the first RET after its address.
+pub fn word_end(opcodes: &[Opcode], start: usize) -> usize {
+    if start >= opcodes.len() {
+        return opcodes.len();
+    }
+    opcodes[start..]
+        .iter()
+        .position(|op| matches!(op, Opcode::RET))
+        .map(|i| start + i + 1)
+        .unwrap_or_else(|| opcodes.len())
+}
+
+/// The addresses a run of opcodes calls, a call compiles to `LDI(address) CALL`
+pub fn called_addresses(opcodes: &[Opcode]) -> Vec<usize> {
+    opcodes
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Opcode::LDI(address), Opcode::CALL] => usize::try_from(*address).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Which word each compiled address belongs to
+#[derive(Debug, Clone, Default)]
+pub struct WordMap {
+    /// Every word's opcodes and its name, in address order
+    words: Vec<(Range<usize>, String)>,
+}
+
+impl WordMap {
+    pub fn new(opcodes: &[Opcode], word_addresses: &HashMap<String, usize>) -> WordMap {
+        let mut words: Vec<(Range<usize>, String)> = word_addresses
+            .iter()
+            .map(|(name, start)| (*start..word_end(opcodes, *start), name.clone()))
+            .collect();
+        words.sort_by(|(a, a_name), (b, b_name)| (a.start, a_name).cmp(&(b.start, b_name)));
+        // Only one name per address, the same code can't be told apart
+        words.dedup_by_key(|(range, _)| range.start);
+        WordMap { words }
+    }
+
+    /// The word whose opcodes include this address
+    pub fn word_at(&self, address: usize) -> Option<&str> {
+        let i = self
+            .words
+            .partition_point(|(range, _)| range.start <= address)
+            .checked_sub(1)?;
+        let (range, name) = &self.words[i];
+        if range.contains(&address) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+/// One line per opcode in `range` with its address. Where each word starts and
+/// ends is marked, and calls are annotated with the word they call.
+pub fn disassemble(
+    opcodes: &[Opcode],
+    word_addresses: &HashMap<String, usize>,
+    range: Range<usize>,
+) -> Vec<String> {
+    let mut names: HashMap<usize, Vec<&str>> = HashMap::new();
+    for (name, address) in word_addresses.iter() {
+        names.entry(*address).or_default().push(name);
+    }
+    for n in names.values_mut() {
+        n.sort_unstable();
+    }
+    let name_at = |address: usize| names.get(&address).map(|n| n.join(", "));
+
+    // The word being shown, and where it ends
+    let mut current = names
+        .keys()
+        .filter(|a| **a < range.start)
+        .max()
+        .map(|a| (name_at(*a).unwrap_or_default(), word_end(opcodes, *a)))
+        .filter(|(_, end)| *end > range.start);
+
+    // Anything after the last word is what the last line executed compiled to
+    let compiled = compiled_length(opcodes, word_addresses.values().copied());
+
+    let mut lines = Vec::new();
+    for address in range.start..range.end.min(opcodes.len()) {
+        if let Some(name) = name_at(address) {
+            lines.push(format!("{}:", name));
+            current = Some((name, word_end(opcodes, address)));
+        } else if address == compiled {
+            lines.push("(last line executed):".to_owned());
+        }
+
+        let op = &opcodes[address];
+        let mut notes = Vec::new();
+        if let (Opcode::CALL, Some(Opcode::LDI(target))) =
+            (op, address.checked_sub(1).map(|a| &opcodes[a]))
+        {
+            let name = usize::try_from(*target).ok().and_then(&name_at);
+            notes.push(format!(
+                "-> {}",
+                name.unwrap_or_else(|| format!("address {}", target))
+            ));
+        }
+        if let Some((name, end)) = current.as_ref() {
+            if address + 1 == *end {
+                notes.push(format!("end of {}", name));
+                current = None;
+            }
+        }
+
+        let line = format!(
+            "{:>6}  {:<16}{}",
+            address,
+            format!("{:?}", op),
+            notes.join(", ")
+        );
+        lines.push(line.trim_end().to_owned());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::compiler;
+
+    #[test]
+    fn disassemble_labels_words_and_calls() {
+        let fc = compiler(": dbl DUP ADD ; : quad dbl dbl ;");
+        let opcodes = &fc.sm.st.opcodes;
+        let lines = |range| disassemble(opcodes, &fc.word_addresses, range);
+
+        assert_eq!(
+            lines(0..opcodes.len()),
+            vec![
+                "dbl:",
+                "     0  DUP",
+                "     1  ADD",
+                "     2  RET             end of dbl",
+                "quad:",
+                "     3  LDI(0)",
+                "     4  CALL            -> dbl",
+                "     5  LDI(0)",
+                "     6  CALL            -> dbl",
+                "     7  RET             end of quad",
+                "(last line executed):",
+                "     8  RET",
+            ]
+        );
+        // Starting part way through a word still marks where it ends
+        assert_eq!(
+            lines(1..4),
+            vec![
+                "     1  ADD",
+                "     2  RET             end of dbl",
+                "quad:",
+                "     3  LDI(0)",
+            ]
+        );
+        // Past the end is cut short
+        assert_eq!(lines(8..100), vec!["(last line executed):", "     8  RET"]);
+        assert!(lines(100..200).is_empty());
+    }
+}
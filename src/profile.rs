@@ -0,0 +1,190 @@
+use crate::debugger::Observer;
+use crate::error::ForthInteractiveError;
+use crate::machine::Machine;
+use crate::opcode::{Opcode, WordMap};
+use std::collections::HashMap;
+
+/// What running some Forth cost one word
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WordProfile {
+    pub calls: u64,
+    /// Opcodes run by the word and everything it called
+    pub inclusive: u64,
+    /// Opcodes run by the word itself
+    pub exclusive: u64,
+}
+
+/// A column of the profile report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileColumn {
+    Word,
+    Calls,
+    Inclusive,
+    Exclusive,
+    Gas,
+}
+
+impl ProfileColumn {
+    pub const NAMES: [&'static str; 5] = ["word", "calls", "inclusive", "exclusive", "gas"];
+
+    pub fn parse(name: &str) -> Option<ProfileColumn> {
+        match name.to_ascii_lowercase().as_str() {
+            "word" => Some(ProfileColumn::Word),
+            "calls" => Some(ProfileColumn::Calls),
+            "inclusive" => Some(ProfileColumn::Inclusive),
+            "exclusive" => Some(ProfileColumn::Exclusive),
+            "gas" => Some(ProfileColumn::Gas),
+            _ => None,
+        }
+    }
+}
+
+/// Counts the opcodes each word runs, gathered by watching the debugger
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    words: HashMap<String, WordProfile>,
+    total: u64,
+    /// Whether the last opcode was a CALL, so the next one starts a word
+    entering: bool,
+}
+
+impl Profile {
+    /// Every opcode that was run, which is the gas used
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn word(&self, word: &str) -> Option<&WordProfile> {
+        self.words.get(word)
+    }
+
+    /// Every word that ran, ordered by a column, biggest first for the numbers
+    pub fn rows(&self, sort: ProfileColumn) -> Vec<(&str, &WordProfile)> {
+        let mut rows: Vec<(&str, &WordProfile)> =
+            self.words.iter().map(|(w, p)| (w.as_str(), p)).collect();
+        rows.sort_by(|(a_word, a), (b_word, b)| {
+            let order = match sort {
+                ProfileColumn::Word => a_word.cmp(b_word),
+                ProfileColumn::Calls => b.calls.cmp(&a.calls),
+                ProfileColumn::Inclusive => b.inclusive.cmp(&a.inclusive),
+                // The gas share is the exclusive count as a fraction of the total
+                ProfileColumn::Exclusive | ProfileColumn::Gas => b.exclusive.cmp(&a.exclusive),
+            };
+            order.then_with(|| a_word.cmp(b_word))
+        });
+        rows
+    }
+
+    /// The profile as a table, one row per word
+    pub fn report(&self, sort: ProfileColumn) -> Vec<String> {
+        let rows = self.rows(sort);
+        let width = rows
+            .iter()
+            .map(|(w, _)| w.chars().count())
+            .chain(std::iter::once("word".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut lines = vec![format!(
+            "{:width$}  {:>8}  {:>10}  {:>10}  {:>7}",
+            "word",
+            "calls",
+            "inclusive",
+            "exclusive",
+            "gas",
+            width = width
+        )];
+        for (word, p) in rows {
+            let share = if self.total == 0 {
+                0.0
+            } else {
+                p.exclusive as f64 * 100.0 / self.total as f64
+            };
+            lines.push(format!(
+                "{:width$}  {:>8}  {:>10}  {:>10}  {:>6.1}%",
+                word,
+                p.calls,
+                p.inclusive,
+                p.exclusive,
+                share,
+                width = width
+            ));
+        }
+        lines.push(format!("Total gas used: {}", self.total));
+        lines
+    }
+}
+
+impl Observer for Profile {
+    fn observe(&mut self, machine: &Machine, words: &WordMap) -> Result<(), ForthInteractiveError> {
+        // The RET that ends the text stops the machine without using gas
+        if matches!(machine.current(), Some(Opcode::RET)) && machine.return_stack.is_empty() {
+            return Ok(());
+        }
+
+        let word = words.word_at(machine.pc).unwrap_or("-");
+        let first = self.total == 0;
+
+        let entry = self.words.entry(word.to_owned()).or_default();
+        entry.exclusive += 1;
+        if first || self.entering {
+            entry.calls += 1;
+        }
+
+        // Every word with a call in progress is paying for this opcode, once
+        // each however deeply it has recursed
+        let mut callers: Vec<&str> = machine
+            .return_stack
+            .iter()
+            .filter_map(|a| words.word_at(*a))
+            .chain(std::iter::once(word))
+            .collect();
+        callers.sort_unstable();
+        callers.dedup();
+        for caller in callers {
+            self.words.entry(caller.to_owned()).or_default().inclusive += 1;
+        }
+
+        self.total += 1;
+        self.entering = matches!(machine.current(), Some(Opcode::CALL));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::{Debugger, RunUntil, Stopped};
+    use crate::history::WordHistory;
+    use crate::test_util::compiler;
+
+    #[test]
+    fn counts_calls_and_opcodes_per_word() {
+        let mut fc = compiler(": dbl DUP ADD ; : quad dbl dbl ;");
+        let mut debugger =
+            Debugger::start(&mut fc, &WordHistory::default(), "1 quad", None).unwrap();
+        let mut profile = Profile::default();
+        let traps = &mut fc.sm.trap_handlers;
+        let stopped = debugger
+            .run(RunUntil::Continue, &[], traps, Some(&mut profile))
+            .unwrap();
+
+        let counts = |calls, inclusive, exclusive| WordProfile {
+            calls,
+            inclusive,
+            exclusive,
+        };
+        assert_eq!(profile.word("dbl"), Some(&counts(2, 6, 6)));
+        assert_eq!(profile.word("quad"), Some(&counts(1, 11, 5)));
+        assert_eq!(profile.word("<debug>"), Some(&counts(1, 14, 3)));
+        assert_eq!(profile.total(), 14);
+        assert_eq!(stopped, Stopped::Finished { gas_used: 14 });
+
+        let order: Vec<&str> = profile
+            .rows(ProfileColumn::Exclusive)
+            .into_iter()
+            .map(|(w, _)| w)
+            .collect();
+        assert_eq!(order, vec!["dbl", "quad", "<debug>"]);
+    }
+}
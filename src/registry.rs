@@ -0,0 +1,222 @@
+use crate::command::{CommandHandled, HandleCommand};
+use crate::error::ForthInteractiveError;
+use crate::session::ReplContext;
+use std::collections::BTreeMap;
+
+/// The commands a session knows about, looked up by id or alias. A command can
+/// also be run by any prefix of its names that only matches that one command,
+/// `list_w` runs `list_words`.
+#[derive(Default)]
+pub struct CommandRegistry {
+    /// Kept in registration order for listing
+    handlers: Vec<Box<dyn HandleCommand>>,
+    /// Every id and alias, and the handler it belongs to
+    names: BTreeMap<String, usize>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry::default()
+    }
+
+    /// Add a command, fails if its id or one of its aliases is already taken
+    pub fn register(
+        &mut self,
+        handler: Box<dyn HandleCommand>,
+    ) -> Result<(), ForthInteractiveError> {
+        let mut names = vec![handler.command_id()];
+        names.extend(handler.aliases());
+
+        for (i, name) in names.iter().enumerate() {
+            if self.names.contains_key(name) || names[..i].contains(name) {
+                return Err(ForthInteractiveError::DuplicateCommand(name.clone()));
+            }
+        }
+
+        let index = self.handlers.len();
+        for name in names {
+            self.names.insert(name, index);
+        }
+        self.handlers.push(handler);
+        Ok(())
+    }
+
+    /// Find the command a name or unambiguous prefix refers to
+    pub fn resolve(&self, name: &str) -> Result<&dyn HandleCommand, ForthInteractiveError> {
+        let index = self.resolve_index(name)?;
+        Ok(self.handlers[index].as_ref())
+    }
+
+    fn resolve_index(&self, name: &str) -> Result<usize, ForthInteractiveError> {
+        if let Some(index) = self.names.get(name) {
+            return Ok(*index);
+        }
+
+        let mut matches: Vec<usize> = self
+            .names
+            .range(name.to_owned()..)
+            .take_while(|(n, _)| n.starts_with(name))
+            .map(|(_, index)| *index)
+            .collect();
+        matches.sort_unstable();
+        matches.dedup();
+
+        match matches.as_slice() {
+            [index] => Ok(*index),
+            [] => Err(ForthInteractiveError::UnknownCommand {
+                name: name.to_owned(),
+                suggestion: self.suggest(name),
+            }),
+            _ => Err(ForthInteractiveError::AmbiguousCommand {
+                prefix: name.to_owned(),
+                candidates: matches
+                    .iter()
+                    .map(|i| self.handlers[*i].command_id())
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Run the one command a name refers to
+    pub fn dispatch(
+        &mut self,
+        name: &str,
+        parameters: &[&str],
+        ctx: &mut ReplContext,
+    ) -> Result<CommandHandled, ForthInteractiveError> {
+        let index = self.resolve_index(name)?;
+        let handler = &mut self.handlers[index];
+        let command_id = handler.command_id();
+        handler.handle_command(&command_id, parameters, ctx)
+    }
+
+    /// The closest name to one that isn't registered, if any is close enough
+    /// to be a likely typo
+    pub fn suggest(&self, name: &str) -> Option<String> {
+        let allowed = (name.chars().count() / 3).max(1);
+        self.names
+            .keys()
+            .map(|n| (edit_distance(name, n), n))
+            .filter(|(distance, _)| *distance <= allowed)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, n)| self.handlers[self.names[n]].command_id())
+    }
+
+    pub fn handlers(&self) -> impl Iterator<Item = &dyn HandleCommand> {
+        self.handlers.iter().map(|h| h.as_ref())
+    }
+
+    /// Every id and alias
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(|n| n.as_str())
+    }
+}
+
+/// Levenshtein distance between two strings, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandHandler;
+
+    fn handler(id: &str, aliases: &[&str]) -> Box<dyn HandleCommand> {
+        Box::new(
+            CommandHandler::new(id, "", "", |_, _, _| Ok(CommandHandled::Handled))
+                .with_aliases(aliases),
+        )
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+        registry.register(handler("list_words", &[])).unwrap();
+        registry.register(handler("list_files", &[])).unwrap();
+        registry.register(handler("load", &["l"])).unwrap();
+        registry.register(handler("undo", &[])).unwrap();
+        registry
+    }
+
+    fn resolved(registry: &CommandRegistry, name: &str) -> Result<String, ForthInteractiveError> {
+        registry.resolve(name).map(|h| h.command_id())
+    }
+
+    #[test]
+    fn names_resolve_by_unique_prefix() {
+        let registry = registry();
+        for (name, id) in [
+            ("undo", "undo"),
+            ("u", "undo"),
+            ("list_w", "list_words"),
+            ("lo", "load"),
+            // An exact alias wins over the names it is a prefix of
+            ("l", "load"),
+        ] {
+            assert_eq!(resolved(&registry, name).unwrap(), id, "{}", name);
+        }
+
+        match resolved(&registry, "list") {
+            Err(ForthInteractiveError::AmbiguousCommand { candidates, .. }) => {
+                assert_eq!(candidates, vec!["list_words", "list_files"]);
+            }
+            result => panic!("expected list to be ambiguous, got {:?}", result),
+        }
+        assert!(matches!(
+            resolved(&registry, "redo"),
+            Err(ForthInteractiveError::UnknownCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn names_can_only_be_taken_once() {
+        let mut registry = registry();
+        for (id, aliases, taken) in [
+            ("undo", &[][..], "undo"),
+            ("lookup", &["l"][..], "l"),
+            ("redo", &["r", "redo"][..], "redo"),
+        ] {
+            match registry.register(handler(id, aliases)) {
+                Err(ForthInteractiveError::DuplicateCommand(name)) => assert_eq!(name, taken),
+                result => panic!("expected {} to be taken, got {:?}", taken, result),
+            }
+        }
+        // A failed registration leaves nothing behind
+        assert!(registry.resolve("lookup").is_err());
+        assert!(registry.resolve("r").is_err());
+    }
+
+    #[test]
+    fn typos_get_a_suggestion() {
+        let registry = registry();
+        for (name, suggestion) in [
+            ("lsit_words", Some("list_words")),
+            ("undp", Some("undo")),
+            ("lod", Some("load")),
+            // Close to an alias suggests the command it belongs to
+            ("k", Some("load")),
+            ("xyzzy", None),
+        ] {
+            assert_eq!(registry.suggest(name).as_deref(), suggestion, "{}", name);
+        }
+
+        match resolved(&registry, "lsit_words") {
+            Err(ForthInteractiveError::UnknownCommand { suggestion, .. }) => {
+                assert_eq!(suggestion.as_deref(), Some("list_words"));
+            }
+            result => panic!("expected an unknown command, got {:?}", result),
+        }
+    }
+}
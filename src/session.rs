@@ -0,0 +1,1313 @@
+use crate::command::{CommandHandled, HandleCommand};
+use crate::commands;
+use crate::debugger::{Breakpoint, Debugger, Observer, RunUntil, Stopped};
+use crate::dictionary::callers_of;
+use crate::error::{ForthInteractiveError, SourceLocation};
+use crate::helper::ReplHelper;
+use crate::history::{Marker, WordHistory, WordVersion};
+use crate::include::{resolve_include, same_file};
+use crate::profile::Profile;
+use crate::registry::CommandRegistry;
+use crate::snapshot::Snapshot;
+use crate::source::{
+    classify, defines_words, directives, statements, Directive, Statement, TokenKind,
+};
+use crate::stack::{format_stack, NumberBase};
+use crate::trace::Trace;
+use crate::undo::{UndoHistory, UndoState};
+use crate::watch::{LoadedFile, Watch, WatchedFile, WordChanges};
+use rust_forth_compiler::{ForthCompiler, ForthError, GasLimit};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The text a command produced, one entry per line.
+///
+/// Handlers write here instead of straight to stdout so that programs embedding
+/// a ReplSession can decide what to do with it.
+#[derive(Debug, Default)]
+pub struct Output {
+    lines: Vec<String>,
+}
+
+impl Output {
+    pub fn println<S: Into<String>>(&mut self, line: S) {
+        self.lines.push(line.into());
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for l in self.lines.iter() {
+            writeln!(f, "{}", l)?;
+        }
+        Ok(())
+    }
+}
+
+/// How lines of input are split between REPL commands and Forth text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DispatchMode {
+    /// Every line is a REPL command, Forth text has to be entered with `i`
+    CommandsOnly,
+    /// Lines starting with the prefix (immediately followed by the command, `\n`)
+    /// are REPL commands, every other line is executed as Forth.
+    ForthByDefault { command_prefix: char },
+}
+
+impl Default for DispatchMode {
+    fn default() -> DispatchMode {
+        DispatchMode::ForthByDefault {
+            command_prefix: '\\',
+        }
+    }
+}
+
+impl DispatchMode {
+    /// If this line is a REPL command return it with any prefix removed
+    pub fn command_text<'l>(&self, line: &'l str) -> Option<&'l str> {
+        match self {
+            DispatchMode::CommandsOnly => Some(line),
+            DispatchMode::ForthByDefault { command_prefix } => {
+                let rest = line.trim_start().strip_prefix(*command_prefix)?;
+                // A prefix followed by whitespace is left to Forth, so `: word ... ;` still works with `:`
+                match rest.chars().next() {
+                    Some(c) if !c.is_whitespace() => Some(rest),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// How a command should be written when shown to the user
+    pub fn display_command(&self, command_id: &str) -> String {
+        match self {
+            DispatchMode::CommandsOnly => command_id.to_owned(),
+            DispatchMode::ForthByDefault { command_prefix } => {
+                format!("{}{}", command_prefix, command_id)
+            }
+        }
+    }
+}
+
+/// The gas limit a new session starts out with
+pub const DEFAULT_GAS_LIMIT: u64 = 100;
+
+/// Parse a gas limit as typed by the user, either a number or `unlimited`.
+/// Returns None for unlimited.
+pub fn parse_gas_limit(text: &str) -> Result<Option<u64>, ForthInteractiveError> {
+    if text.eq_ignore_ascii_case("unlimited") {
+        return Ok(None);
+    }
+    match text.parse::<u64>() {
+        Ok(n) => Ok(Some(n)),
+        Err(_) => Err(ForthInteractiveError::InvalidGasLimit(text.to_owned())),
+    }
+}
+
+/// Everything a command handler is allowed to touch while it runs.
+pub struct ReplContext {
+    pub fc: ForthCompiler,
+    /// Gas available to each execution, None means unlimited
+    pub gas_limit: Option<u64>,
+    /// Forth text being run an opcode at a time, if any
+    pub debugger: Option<Debugger>,
+    /// While there are any, Forth lines are run by the debugger so they can stop at them
+    pub breakpoints: Vec<Breakpoint>,
+    /// While tracing, everything that doesn't define words is run by the
+    /// debugger so each opcode can be logged
+    pub trace: Option<Trace>,
+    /// The last profile taken, so it can be shown sorted another way
+    pub profile: Option<Profile>,
+    /// How the number stack is shown
+    pub number_base: NumberBase,
+    /// Show the number stack after every line of Forth
+    pub show_stack: bool,
+    /// Earlier definitions of the words that have been redefined
+    pub word_history: WordHistory,
+    /// Checkpoints to roll the dictionary back to, oldest first
+    pub markers: Vec<Marker>,
+    /// The states before the lines that changed anything, for undo and redo
+    pub undo: UndoHistory,
+    /// Every file loaded so far, in the order they were first loaded
+    pub loaded_files: Vec<LoadedFile>,
+    /// Files to reload when they change
+    pub watch: Option<Watch>,
+    /// Where INCLUDE and REQUIRE look for files that aren't next to the file
+    /// including them
+    pub include_path: Vec<PathBuf>,
+    /// The files being loaded right now, outermost first, with their canonical
+    /// paths so an include cycle can be caught
+    including: Vec<(PathBuf, String)>,
+    output: Output,
+}
+
+impl ReplContext {
+    pub fn println<S: Into<String>>(&mut self, line: S) {
+        self.output.println(line);
+    }
+
+    /// Execute Forth text with the session gas limit
+    pub fn execute(&mut self, text: &str) -> Result<(), ForthInteractiveError> {
+        self.execute_with_gas(text, self.gas_limit)
+    }
+
+    /// Execute Forth text with a specific gas limit, None means unlimited
+    pub fn execute_with_gas(
+        &mut self,
+        text: &str,
+        gas_limit: Option<u64>,
+    ) -> Result<(), ForthInteractiveError> {
+        // rust-forth-tokenizer panics on text ending in a word and a single
+        // whitespace character, which a line or a statement from a file can
+        let text = text.trim();
+
+        // Only text with definitions can replace one
+        let before = if defines_words(text) {
+            Some(WordVersion::all(&self.fc))
+        } else {
+            None
+        };
+
+        let result = if self.trace.is_some() && Debugger::can_debug(text) {
+            self.execute_traced(text, gas_limit)
+        } else {
+            self.execute_compiled(text, gas_limit)
+        };
+
+        // A definition is compiled even if running the rest of the text fails
+        if let Some(before) = before {
+            self.note_redefinitions(before);
+        }
+        result
+    }
+
+    /// Execute Forth text with the compiler's own stack machine
+    fn execute_compiled(
+        &mut self,
+        text: &str,
+        gas_limit: Option<u64>,
+    ) -> Result<(), ForthInteractiveError> {
+        let limit = match gas_limit {
+            Some(n) => GasLimit::Limited(n),
+            None => GasLimit::Unlimited,
+        };
+        match self.fc.execute_string(text, limit) {
+            Ok(()) => Ok(()),
+            Err(ForthError::RanOutOfGas) => Err(ForthInteractiveError::RanOutOfGas {
+                gas_used: self.fc.sm.st.gas_used(),
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Warn about every word that was redefined, and which words still call its
+    /// old definition, and keep the old definition in the word history
+    fn note_redefinitions(&mut self, before: HashMap<String, WordVersion>) {
+        let mut redefined: Vec<(String, WordVersion)> = before
+            .into_iter()
+            .filter(|(w, old)| {
+                self.fc
+                    .word_addresses
+                    .get(w)
+                    .map(|address| *address != old.address)
+                    .unwrap_or(false)
+            })
+            .collect();
+        redefined.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (word, old) in redefined {
+            let mut stale = callers_of(&self.fc, old.address);
+            stale.retain(|w| *w != word);
+            if stale.is_empty() {
+                self.println(format!("Warning: {} redefined", word));
+            } else {
+                let verb = if stale.len() == 1 { "calls" } else { "call" };
+                self.println(format!(
+                    "Warning: {} redefined, {} still {} the old definition",
+                    word,
+                    stale.join(", "),
+                    verb
+                ));
+            }
+            self.word_history.record(&word, old);
+        }
+    }
+
+    /// Put the compiler into the state of a snapshot, anything tied to the old
+    /// state is dropped. Trap handlers stay registered.
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), ForthInteractiveError> {
+        snapshot.restore_into(&mut self.fc)?;
+        self.debugger = None;
+        self.word_history.clear();
+        self.markers.clear();
+        self.loaded_files.clear();
+        Ok(())
+    }
+
+    /// Capture the compiler, keeping the opcodes of earlier definitions that
+    /// revert can still bring back
+    pub fn capture(&self) -> Snapshot {
+        Snapshot::capture_keeping(&self.fc, self.word_history.addresses())
+    }
+
+    /// Remove every word compiled at or after an address, and the opcodes from
+    /// there on. A removed word with an earlier definition before the address
+    /// goes back to that definition instead. Returns the words that were removed.
+    pub fn forget_from(&mut self, boundary: usize) -> Result<Vec<String>, ForthInteractiveError> {
+        let mut snapshot = self.capture();
+        let mut forgotten: Vec<String> = snapshot
+            .word_addresses
+            .iter()
+            .filter(|(_, address)| **address >= boundary)
+            .map(|(w, _)| w.clone())
+            .collect();
+        forgotten.sort();
+
+        for w in forgotten.iter() {
+            snapshot.word_addresses.remove(w);
+            snapshot.word_definitions.remove(w);
+            snapshot.word_opcodes.remove(w);
+        }
+        snapshot.opcodes.truncate(boundary);
+        snapshot.restore_into(&mut self.fc)?;
+
+        let mut restored = Vec::new();
+        for w in forgotten.iter() {
+            let earlier = self
+                .word_history
+                .versions(w)
+                .iter()
+                .rposition(|v| v.address < boundary);
+            if let Some(version) = earlier.and_then(|i| self.word_history.take(w, i + 1)) {
+                version.install(&mut self.fc, w);
+                restored.push(format!(
+                    "{} is back to its definition at @{}",
+                    w, version.address
+                ));
+            }
+        }
+        forgotten.retain(|w| !self.fc.word_addresses.contains_key(w));
+
+        self.debugger = None;
+        self.word_history.retain_below(boundary);
+        self.markers
+            .retain(|m| m.snapshot.opcodes.len() <= boundary);
+        // A file loaded at or after the boundary lost every word it defined
+        self.loaded_files
+            .retain(|f| f.before.opcodes.len() < boundary);
+        for line in restored {
+            self.println(line);
+        }
+        Ok(forgotten)
+    }
+
+    /// Set a checkpoint the dictionary can be rolled back to, replacing any
+    /// marker with the same name
+    pub fn set_marker(&mut self, name: &str) {
+        self.markers.retain(|m| m.name != name);
+        self.markers.push(Marker {
+            name: name.to_owned(),
+            snapshot: self.capture(),
+            history: self.word_history.clone(),
+            loaded_files: self.loaded_files.clone(),
+        });
+    }
+
+    /// Put the dictionary back the way it was when a marker was set, the
+    /// number stack is left alone. The marker and any set after it are removed.
+    pub fn rollback(&mut self, name: &str) -> Result<(), ForthInteractiveError> {
+        let i = self
+            .markers
+            .iter()
+            .position(|m| m.name == name)
+            .ok_or_else(|| ForthInteractiveError::UnknownMarker(name.to_owned()))?;
+
+        let mut snapshot = self.markers[i].snapshot.clone();
+        snapshot.number_stack = self.fc.sm.st.number_stack.clone();
+        snapshot.restore_into(&mut self.fc)?;
+        self.debugger = None;
+        self.word_history = self.markers[i].history.clone();
+        self.loaded_files = self.markers[i].loaded_files.clone();
+        self.markers.truncate(i);
+        Ok(())
+    }
+
+    /// Everything undo has to put back to return to this point
+    pub fn undo_state(&self) -> UndoState {
+        UndoState {
+            snapshot: self.capture(),
+            word_history: self.word_history.clone(),
+            loaded_files: self.loaded_files.clone(),
+        }
+    }
+
+    /// Go back to the state before the last line that changed anything.
+    /// Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> Result<bool, ForthInteractiveError> {
+        let current = self.undo_state();
+        match self.undo.undo(current) {
+            Some(state) => self.return_to(state).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Put back the state the last undo left. Returns false if there was
+    /// nothing to redo.
+    pub fn redo(&mut self) -> Result<bool, ForthInteractiveError> {
+        let current = self.undo_state();
+        match self.undo.redo(current) {
+            Some(state) => self.return_to(state).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    fn return_to(&mut self, state: UndoState) -> Result<(), ForthInteractiveError> {
+        state.snapshot.restore_into(&mut self.fc)?;
+        self.debugger = None;
+        self.word_history = state.word_history;
+        self.loaded_files = state.loaded_files;
+        Ok(())
+    }
+
+    /// Run Forth text to the end on the debugger's machine, logging every opcode
+    fn execute_traced(
+        &mut self,
+        text: &str,
+        gas_limit: Option<u64>,
+    ) -> Result<(), ForthInteractiveError> {
+        let mut debugger = Debugger::start(&mut self.fc, &self.word_history, text, gas_limit)?;
+        let trace = self.trace.as_mut().map(|t| t as &mut dyn Observer);
+        let traps = &mut self.fc.sm.trap_handlers;
+        let result = debugger.run(RunUntil::Continue, &[], traps, trace);
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+            for line in trace.take_lines() {
+                self.output.println(line);
+            }
+        }
+
+        // Whatever the text did up to an error stays on the stack, as it does
+        // when the compiler runs it
+        debugger.write_stacks(&mut self.fc);
+        result.map(|_| ())
+    }
+
+    /// Run the debugger and show where it stopped. Once the text is done its
+    /// number stack becomes the session's.
+    pub fn run_debugger(&mut self, until: RunUntil) -> Result<Stopped, ForthInteractiveError> {
+        let debugger = self
+            .debugger
+            .as_mut()
+            .ok_or(ForthInteractiveError::NotDebugging)?;
+        let trace = self.trace.as_mut().map(|t| t as &mut dyn Observer);
+        let traps = &mut self.fc.sm.trap_handlers;
+        let result = debugger.run(until, &self.breakpoints, traps, trace);
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+            for line in trace.take_lines() {
+                self.output.println(line);
+            }
+        }
+        let stopped = match result {
+            Ok(stopped) => stopped,
+            // The compiler leaves whatever the text did up to the error on the stack
+            Err(err) => {
+                debugger.write_stacks(&mut self.fc);
+                self.debugger = None;
+                return Err(err);
+            }
+        };
+
+        let mut lines = Vec::new();
+        match stopped {
+            Stopped::Finished { .. } => {
+                debugger.write_stacks(&mut self.fc);
+                self.debugger = None;
+            }
+            Stopped::Breakpoint(id) => {
+                if let Some(b) = self.breakpoints.iter().find(|b| b.id == id) {
+                    lines.push(format!("Breakpoint {}", b));
+                }
+                lines.extend(debugger.describe());
+            }
+            Stopped::Paused => lines.extend(debugger.describe()),
+        }
+        for line in lines {
+            self.println(line);
+        }
+        Ok(stopped)
+    }
+
+    /// Load a Forth source file and execute it with the session gas limit.
+    ///
+    /// The file is run a statement at a time so an error can be tied to the line
+    /// it came from, each statement gets the full gas limit. `INCLUDE file` and
+    /// `REQUIRE file` in the source load other files along the way.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ForthInteractiveError> {
+        let path = path.as_ref();
+        let file = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                return Err(ForthInteractiveError::CouldNotReadFile { path: file, error });
+            }
+        };
+
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(i) = self.including.iter().position(|(p, _)| *p == canonical) {
+            let mut cycle: Vec<String> = self.including[i..]
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect();
+            cycle.push(file);
+            return Err(ForthInteractiveError::IncludeCycle(cycle));
+        }
+
+        // A file loaded again keeps the state from before its first load. It
+        // only counts as loaded once it loads without an error, but goes ahead
+        // of the files it included so reloading it starts from the right state.
+        let first_load = if self.loaded_files.iter().any(|f| f.path == path) {
+            None
+        } else {
+            Some((self.loaded_files.len(), self.capture()))
+        };
+
+        self.including.push((canonical, file.clone()));
+        let result = self.load_source(path, &file, &source);
+        self.including.pop();
+
+        if let (Ok(()), Some((index, before))) = (&result, first_load) {
+            self.loaded_files.insert(
+                index,
+                LoadedFile {
+                    path: path.to_path_buf(),
+                    before,
+                },
+            );
+        }
+        result
+    }
+
+    fn load_source(
+        &mut self,
+        path: &Path,
+        file: &str,
+        source: &str,
+    ) -> Result<(), ForthInteractiveError> {
+        for statement in statements(source) {
+            for directive in directives(statement) {
+                let result = match directive {
+                    Directive::Forth(forth) => self.execute(forth.text),
+                    Directive::Include { file, once } => self.include(path, file.text, once),
+                };
+                if let Err(err) = result {
+                    let location = match directive {
+                        Directive::Forth(forth) => self.locate(file, source, forth, &err),
+                        Directive::Include { file: name, .. } => {
+                            SourceLocation::new(file, source, name.start, name.text)
+                        }
+                    };
+                    return Err(ForthInteractiveError::InSource {
+                        location,
+                        error: Box::new(err),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a file named in another one, `once` skips it if it has been loaded
+    /// already this session
+    fn include(
+        &mut self,
+        including_file: &Path,
+        name: &str,
+        once: bool,
+    ) -> Result<(), ForthInteractiveError> {
+        let path = resolve_include(including_file, name, &self.include_path)?;
+        if once && self.loaded_files.iter().any(|f| same_file(&f.path, &path)) {
+            return Ok(());
+        }
+        self.load_file(path)
+    }
+
+    /// Watch files along with any already being watched, loading the ones that
+    /// haven't been loaded yet. Reloads start from the state before the first of
+    /// them was loaded, or from an empty dictionary if `fresh`.
+    pub fn watch_files(
+        &mut self,
+        paths: &[&str],
+        fresh: bool,
+    ) -> Result<(), ForthInteractiveError> {
+        let mut watched: Vec<PathBuf> = match self.watch.as_ref() {
+            Some(watch) => watch.files.iter().map(|f| f.path.clone()).collect(),
+            None => Vec::new(),
+        };
+        for p in paths.iter().map(PathBuf::from) {
+            if !watched.contains(&p) {
+                watched.push(p);
+            }
+        }
+
+        for p in watched.iter() {
+            if !self.loaded_files.iter().any(|f| f.path == *p) {
+                self.load_file(p)?;
+            }
+        }
+
+        // Every watched file has been loaded by now, reload them in the same order
+        let loaded = &self.loaded_files;
+        let position = |p: &PathBuf| loaded.iter().position(|f| f.path == *p);
+        watched.sort_by_key(position);
+        let fresh = fresh || self.watch.as_ref().map(|w| w.fresh).unwrap_or(false);
+        let (base, base_files) = match watched.first().and_then(position) {
+            Some(i) if !fresh => (loaded[i].before.clone(), i),
+            _ => (Snapshot::default(), 0),
+        };
+
+        let first = watched.first().and_then(position).unwrap_or(base_files);
+        self.watch = Some(Watch {
+            files: watched.iter().map(WatchedFile::new).collect(),
+            included: self.included_by_watched(&watched, first),
+            base,
+            base_files,
+            fresh,
+        });
+        Ok(())
+    }
+
+    /// The files loaded from a point on that aren't being watched themselves,
+    /// which the watched files loaded with INCLUDE or REQUIRE
+    fn included_by_watched(&self, watched: &[PathBuf], from: usize) -> Vec<WatchedFile> {
+        self.loaded_files[from.min(self.loaded_files.len())..]
+            .iter()
+            .filter(|f| !watched.contains(&f.path))
+            .map(|f| WatchedFile::new(&f.path))
+            .collect()
+    }
+
+    /// Reload the watched files if any of them changed, or any file they
+    /// included, and show which words changed. The number stack is left as it
+    /// is. If a file fails to load the session is left as it was.
+    /// Returns whether anything was reloaded.
+    pub fn reload_watched(&mut self) -> Result<bool, ForthInteractiveError> {
+        let watch = match self.watch.as_mut() {
+            Some(watch) => watch,
+            None => return Ok(false),
+        };
+        if !watch.changed() {
+            return Ok(false);
+        }
+        let mut base = watch.base.clone();
+        let base_files = watch.base_files;
+        let paths: Vec<PathBuf> = watch.files.iter().map(|f| f.path.clone()).collect();
+
+        let before = self.undo_state();
+        let definitions = self.fc.word_definitions.clone();
+        // Like a rollback, the number stack is left alone
+        base.number_stack = self.fc.sm.st.number_stack.clone();
+        base.restore_into(&mut self.fc)?;
+        self.debugger = None;
+        // Anything compiled after the base is being thrown away, including the
+        // files loaded on top of it, so REQUIRE loads them again
+        let boundary = base.opcodes.len();
+        self.word_history.retain_below(boundary);
+        let loaded_files = self.loaded_files.clone();
+        self.loaded_files.truncate(base_files);
+        for p in paths.iter() {
+            if let Err(err) = self.load_file(p) {
+                self.loaded_files = loaded_files;
+                self.return_to(before)?;
+                return Err(err);
+            }
+        }
+        self.markers
+            .retain(|m| m.snapshot.opcodes.len() <= boundary);
+        self.undo.record(before);
+        // A file can INCLUDE different files now
+        let included = self.included_by_watched(&paths, base_files);
+        if let Some(watch) = self.watch.as_mut() {
+            watch.included = included;
+        }
+
+        let names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        self.println(format!("Reloaded {}", names.join(", ")));
+        for line in WordChanges::between(&definitions, &self.fc.word_definitions).lines() {
+            self.println(line);
+        }
+        Ok(true)
+    }
+
+    /// Work out which token in a failed statement is to blame. A word the
+    /// compiler doesn't know stops it before anything runs, otherwise all we can
+    /// say is which statement failed.
+    fn locate(
+        &self,
+        file: &str,
+        source: &str,
+        statement: Statement,
+        err: &ForthInteractiveError,
+    ) -> SourceLocation {
+        let tokens = classify(statement.text, |w| self.fc.word_addresses.contains_key(w));
+        let named = match err {
+            ForthInteractiveError::ForthError(ForthError::UnknownToken(word)) => tokens
+                .iter()
+                .find(|(token, kind)| token.text == word && *kind != TokenKind::DefinedName),
+            _ => None,
+        };
+        let culprit = named
+            .or_else(|| tokens.iter().find(|(_, kind)| *kind == TokenKind::Unknown))
+            .or_else(|| tokens.first());
+
+        match culprit {
+            Some((token, _)) => {
+                SourceLocation::new(file, source, statement.start + token.start, token.text)
+            }
+            None => SourceLocation::new(file, source, statement.start, ""),
+        }
+    }
+
+    /// The names of every word in the dictionary
+    pub fn dictionary_words(&self) -> Vec<String> {
+        self.fc.word_addresses.keys().cloned().collect()
+    }
+
+    /// The number stack in the chosen base, with its depth and top marked
+    pub fn describe_stack(&self) -> String {
+        format_stack(&self.fc.sm.st.number_stack, self.number_base)
+    }
+
+    pub fn describe_gas_limit(&self) -> String {
+        match self.gas_limit {
+            Some(n) => n.to_string(),
+            None => "unlimited".to_owned(),
+        }
+    }
+}
+
+/// A REPL session, owns the Forth compiler, the command handlers and the
+/// history of lines that have been processed.
+pub struct ReplSession {
+    context: ReplContext,
+    commands: CommandRegistry,
+    history: Vec<String>,
+    dispatch_mode: DispatchMode,
+    /// What the last line that failed printed before it did
+    failed_output: Output,
+}
+
+impl Default for ReplSession {
+    fn default() -> ReplSession {
+        ReplSession::new()
+    }
+}
+
+impl ReplSession {
+    /// Create a session with the standard set of commands registered
+    pub fn new() -> ReplSession {
+        let mut session = ReplSession::without_commands();
+        for h in commands::default_handlers() {
+            session
+                .add_handler(h)
+                .expect("the default commands have distinct names");
+        }
+        session
+    }
+
+    /// Create a session with no commands registered at all
+    pub fn without_commands() -> ReplSession {
+        ReplSession {
+            context: ReplContext {
+                fc: ForthCompiler::default(),
+                gas_limit: Some(DEFAULT_GAS_LIMIT),
+                debugger: None,
+                breakpoints: Vec::new(),
+                trace: None,
+                profile: None,
+                number_base: NumberBase::default(),
+                show_stack: false,
+                word_history: WordHistory::default(),
+                markers: Vec::new(),
+                undo: UndoHistory::default(),
+                loaded_files: Vec::new(),
+                watch: None,
+                include_path: Vec::new(),
+                including: Vec::new(),
+                output: Output::default(),
+            },
+            commands: CommandRegistry::new(),
+            history: Vec::new(),
+            dispatch_mode: DispatchMode::default(),
+            failed_output: Output::default(),
+        }
+    }
+
+    /// Register a command, fails if its id or an alias is already taken
+    pub fn add_handler(
+        &mut self,
+        handler: Box<dyn HandleCommand>,
+    ) -> Result<(), ForthInteractiveError> {
+        self.commands.register(handler)
+    }
+
+    pub fn compiler(&self) -> &ForthCompiler {
+        &self.context.fc
+    }
+
+    pub fn compiler_mut(&mut self) -> &mut ForthCompiler {
+        &mut self.context.fc
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// A line editor helper that knows the commands and words in the session
+    /// right now, replace it after each line to keep it current.
+    pub fn helper(&self) -> ReplHelper {
+        let file_commands = self
+            .commands
+            .handlers()
+            .filter(|h| h.takes_file_parameters())
+            .flat_map(|h| {
+                let mut names = h.aliases();
+                names.push(h.command_id());
+                names
+            })
+            .collect();
+
+        ReplHelper::new(
+            self.dispatch_mode,
+            self.commands.names().map(|n| n.to_owned()).collect(),
+            file_commands,
+            self.context.dictionary_words(),
+        )
+    }
+
+    pub fn gas_limit(&self) -> Option<u64> {
+        self.context.gas_limit
+    }
+
+    /// Set the gas available to every later execution, None means unlimited
+    pub fn set_gas_limit(&mut self, gas_limit: Option<u64>) {
+        self.context.gas_limit = gas_limit;
+    }
+
+    pub fn include_path(&self) -> &[PathBuf] {
+        &self.context.include_path
+    }
+
+    /// Add a directory for INCLUDE and REQUIRE to look in, after the ones already there
+    pub fn add_include_path<P: AsRef<Path>>(&mut self, dir: P) {
+        self.context.include_path.push(dir.as_ref().to_path_buf());
+    }
+
+    pub fn dispatch_mode(&self) -> DispatchMode {
+        self.dispatch_mode
+    }
+
+    pub fn set_dispatch_mode(&mut self, dispatch_mode: DispatchMode) {
+        self.dispatch_mode = dispatch_mode;
+    }
+
+    /// Execute Forth text, whatever the dispatch mode
+    pub fn execute(&mut self, text: &str) -> Result<Output, ForthInteractiveError> {
+        let result = self.context.execute(text);
+        self.collect_output(result)
+    }
+
+    /// Load and execute a Forth source file
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Output, ForthInteractiveError> {
+        let result = self.context.load_file(path);
+        self.collect_output(result)
+    }
+
+    /// Reload the watched files if any of them changed since the last call.
+    /// Nothing else reloads them, a program embedding the session calls this
+    /// before each line it processes, as the REPL does.
+    pub fn reload_watched(&mut self) -> Result<Output, ForthInteractiveError> {
+        let result = self.context.reload_watched().map(|_| ());
+        self.collect_output(result)
+    }
+
+    /// Hand back what was printed, partial output from a failed command is kept
+    /// for failed_output
+    fn collect_output(
+        &mut self,
+        result: Result<(), ForthInteractiveError>,
+    ) -> Result<Output, ForthInteractiveError> {
+        let output = std::mem::take(&mut self.context.output);
+        match result {
+            Ok(()) => Ok(output),
+            Err(err) => {
+                self.failed_output = output;
+                Err(err)
+            }
+        }
+    }
+
+    /// What the last failed line printed before it failed, such as the trace
+    /// up to the error. Taking it leaves nothing behind.
+    pub fn take_failed_output(&mut self) -> Output {
+        std::mem::take(&mut self.failed_output)
+    }
+
+    /// Process one line of input and return whatever it printed. Watched files
+    /// aren't reloaded, see reload_watched.
+    pub fn process_line(&mut self, line: &str) -> Result<Output, ForthInteractiveError> {
+        // If nothing to talk about, just ignore...
+        if line.trim().is_empty() {
+            return Ok(Output::default());
+        }
+        self.history.push(line.to_owned());
+
+        let command_text = self.dispatch_mode.command_text(line);
+        let before = if self.undoable(command_text) {
+            Some(self.context.undo_state())
+        } else {
+            None
+        };
+
+        let result = match command_text {
+            Some(command_text) => self.process_command(command_text),
+            None => self.process_forth(line),
+        };
+
+        // Only lines that changed something are worth undoing, failed ones included
+        if let Some(before) = before {
+            if !before.snapshot.matches(&self.context.fc) {
+                self.context.undo.record(before);
+            }
+        }
+        result
+    }
+
+    /// Whether to take a snapshot before a line so it can be undone. Forth
+    /// always is, commands unless they say otherwise, and a command that can't
+    /// be resolved won't change anything.
+    fn undoable(&self, command_text: Option<&str>) -> bool {
+        let command_text = match command_text {
+            Some(command_text) => command_text,
+            None => return true,
+        };
+        command_text
+            .split_whitespace()
+            .next()
+            .and_then(|name| self.commands.resolve(name).ok())
+            .map(|h| h.undoable())
+            .unwrap_or(false)
+    }
+
+    fn process_forth(&mut self, text: &str) -> Result<Output, ForthInteractiveError> {
+        let ctx = &mut self.context;
+        // Breakpoints can only stop text the debugger runs, anything defining words runs as normal
+        let result = if ctx.breakpoints.is_empty() || !Debugger::can_debug(text) {
+            ctx.execute(text).map(|()| true)
+        } else {
+            Debugger::start(&mut ctx.fc, &ctx.word_history, text, ctx.gas_limit)
+                .map(|debugger| ctx.debugger = Some(debugger))
+                .and_then(|()| ctx.run_debugger(RunUntil::Continue))
+                .map(|stopped| matches!(stopped, Stopped::Finished { .. }))
+        };
+        if let Ok(true) = result {
+            if self.context.show_stack {
+                let stack = self.context.describe_stack();
+                self.context.println(stack);
+            }
+            self.context.println("ok");
+        }
+        self.collect_output(result.map(|_| ()))
+    }
+
+    fn process_command(&mut self, line: &str) -> Result<Output, ForthInteractiveError> {
+        // Okay, so we have a line, each line starts with a command, and then has optional parameters
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let command = words[0];
+        let parameters = &words[1..];
+
+        let result = match self
+            .commands
+            .dispatch(command, parameters, &mut self.context)
+        {
+            Ok(CommandHandled::ShowHelp(None)) => {
+                self.show_command_list();
+                Ok(())
+            }
+            Ok(CommandHandled::ShowHelp(Some(topic))) => self.show_command_help(&topic),
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        };
+
+        // Show names the way they have to be typed
+        let mode = self.dispatch_mode;
+        let result = result.map_err(|err| match err {
+            ForthInteractiveError::UnknownCommand { name, suggestion } => {
+                ForthInteractiveError::UnknownCommand {
+                    name: mode.display_command(&name),
+                    suggestion: suggestion.map(|s| mode.display_command(&s)),
+                }
+            }
+            err => err,
+        });
+
+        self.collect_output(result)
+    }
+
+    /// One line for every command, its usage and what it does
+    fn show_command_list(&mut self) {
+        let entries: Vec<(String, String)> = self
+            .commands
+            .handlers()
+            .map(|h| {
+                (
+                    format!(
+                        "{} {}",
+                        self.dispatch_mode.display_command(&h.command_id()),
+                        h.usage_text()
+                    ),
+                    h.help_text(),
+                )
+            })
+            .collect();
+        let width = entries.iter().map(|(u, _)| u.len()).max().unwrap_or(0);
+
+        self.context.println("Commands:");
+        for (usage, help) in entries {
+            self.context
+                .println(format!("    {:width$}  {}", usage, help, width = width));
+        }
+        self.context.println(format!(
+            "Type {} for more about a command.",
+            self.dispatch_mode.display_command("help <command>")
+        ));
+    }
+
+    /// Everything there is to say about one command
+    fn show_command_help(&mut self, topic: &str) -> Result<(), ForthInteractiveError> {
+        let mode = self.dispatch_mode;
+        let h = self.commands.resolve(topic)?;
+
+        let mut lines = vec![
+            format!(
+                "{} {}",
+                mode.display_command(&h.command_id()),
+                h.usage_text()
+            ),
+            format!("    {}", h.help_text()),
+        ];
+        let aliases = h.aliases();
+        if !aliases.is_empty() {
+            let aliases: Vec<String> = aliases.iter().map(|a| mode.display_command(a)).collect();
+            lines.push(format!("    Also: {}", aliases.join(", ")));
+        }
+        if let Some(long_help) = h.long_help() {
+            lines.push(String::new());
+            lines.push(format!("    {}", long_help));
+        }
+        let parameters = h.parameters();
+        if !parameters.is_empty() {
+            lines.push(String::new());
+            lines.push("Parameters:".to_owned());
+            let width = parameters.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
+            for (name, description) in parameters {
+                lines.push(format!(
+                    "    {:width$}  {}",
+                    name,
+                    description,
+                    width = width
+                ));
+            }
+        }
+        let examples = h.examples();
+        if !examples.is_empty() {
+            lines.push(String::new());
+            lines.push("Examples:".to_owned());
+            for e in examples {
+                lines.push(format!("    {}", mode.display_command(&e)));
+            }
+        }
+
+        for l in lines {
+            self.context.println(l);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The number stack after running each line in a new session
+    fn stack_after(lines: &[&str]) -> Vec<i64> {
+        let mut session = ReplSession::new();
+        for line in lines {
+            let _ = session.process_line(line);
+        }
+        session.compiler().sm.st.number_stack.clone()
+    }
+
+    #[test]
+    fn breakpoints_dont_change_results() {
+        let words = ": dbl DUP ADD ; : sum 0 5 0 DO I ADD LOOP ;";
+        for line in [
+            "10 3 SUB dbl sum",
+            "2 3 4 2DUP = 1- 2SWAP",
+            "1 DROP DROP DROP",
+        ] {
+            let plain = stack_after(&[words, "1", line]);
+            assert_eq!(
+                stack_after(&[words, "1", "\\break sum", line, "\\continue"]),
+                plain,
+                "{}",
+                line
+            );
+            assert_eq!(
+                stack_after(&[words, "1", "\\break nowhere", line]),
+                plain,
+                "{}",
+                line
+            );
+            assert_eq!(
+                stack_after(&[words, "1", "\\trace on", line]),
+                plain,
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn running_out_of_gas_reports_the_gas_used() {
+        let line = "1 BEGIN 1+ DUP 0 = UNTIL";
+        let mut used = Vec::new();
+        for trace in [false, true] {
+            let mut session = ReplSession::new();
+            session.set_gas_limit(Some(50));
+            if trace {
+                session.process_line("\\trace on").unwrap();
+            }
+            match session.process_line(line) {
+                Err(ForthInteractiveError::RanOutOfGas { gas_used }) => used.push(gas_used),
+                result => panic!("expected to run out of gas, got {:?}", result.map(|_| ())),
+            }
+        }
+        assert_eq!(used, vec![51, 51]);
+    }
+
+    #[test]
+    fn forget_goes_back_to_earlier_definitions() {
+        let mut session = ReplSession::new();
+        for line in [": x 1 ;", ": a x ;", ": x 2 ;", ": b 3 ;"] {
+            session.process_line(line).unwrap();
+        }
+        let forgotten = session.compiler().word_addresses["x"];
+        session.process_line("\\forget x").unwrap();
+        let fc = session.compiler();
+        assert!(!fc.word_addresses.contains_key("b"));
+        assert_eq!(fc.word_addresses["x"], 0);
+
+        // The next word goes where the forgotten x was
+        session.process_line(": c x a ;").unwrap();
+        assert_eq!(session.compiler().word_addresses["c"], forgotten);
+        session.process_line("c").unwrap();
+        assert_eq!(session.compiler().sm.st.number_stack, vec![1, 1]);
+    }
+
+    #[test]
+    fn rollback_keeps_the_stack() {
+        let mut session = ReplSession::new();
+        for line in [
+            ": x 1 ;",
+            "\\marker m",
+            ": x 2 ;",
+            ": y 3 ;",
+            "\\marker n",
+            "7",
+        ] {
+            session.process_line(line).unwrap();
+        }
+        session.process_line("\\rollback m").unwrap();
+        let fc = session.compiler();
+        assert!(!fc.word_addresses.contains_key("y"));
+        assert_eq!(fc.sm.st.number_stack, vec![7]);
+        session.process_line("x").unwrap();
+        assert_eq!(session.compiler().sm.st.number_stack, vec![7, 1]);
+
+        // Both markers are used up
+        for name in ["m", "n"] {
+            assert!(matches!(
+                session.process_line(&format!("\\rollback {}", name)),
+                Err(ForthInteractiveError::UnknownMarker(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn trap_handlers_outlive_going_back() {
+        use rust_simple_stack_processor::{TrapHandled, TrapHandler};
+
+        let path = std::env::temp_dir().join(format!("traps-{}.snapshot", std::process::id()));
+        let mut session = ReplSession::new();
+        session
+            .compiler_mut()
+            .sm
+            .trap_handlers
+            .push(Box::new(TrapHandler::new(100, |_, st| {
+                st.number_stack.push(42);
+                Ok(TrapHandled::Handled)
+            })));
+        session
+            .process_line(&format!("\\save {}", path.display()))
+            .unwrap();
+
+        for back in [
+            "\\undo".to_owned(),
+            "\\forget x".to_owned(),
+            "\\rollback m".to_owned(),
+            format!("\\restore {}", path.display()),
+        ] {
+            session.process_line("\\marker m").unwrap();
+            session.process_line(": x 1 ;").unwrap();
+            session.process_line(&back).unwrap();
+            session.process_line("100 TRAP").unwrap();
+            assert_eq!(
+                session.compiler_mut().sm.st.number_stack.pop(),
+                Some(42),
+                "{}",
+                back
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn undo_keeps_reverted_definitions() {
+        let mut session = ReplSession::new();
+        for line in [
+            ": x 1 ;",
+            ": x 2 ;",
+            "\\revert x",
+            "5",
+            "\\undo",
+            "\\revert x",
+        ] {
+            session.process_line(line).unwrap();
+        }
+        session.process_line("x").unwrap();
+        assert_eq!(session.compiler().sm.st.number_stack, vec![2]);
+
+        for line in ["\\revert x", "\\debug x", "x", "\\revert x", "x"] {
+            session.process_line(line).unwrap();
+        }
+        assert_eq!(session.compiler().sm.st.number_stack, vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn load_runs_lines_that_arent_definitions() {
+        let dir = std::env::temp_dir().join(format!("load-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.fs"), "3 4\n: x 1 ;\n7 INCLUDE y.fs x y\n").unwrap();
+        fs::write(dir.join("y.fs"), ": y 2 ;\n").unwrap();
+
+        let mut session = ReplSession::new();
+        let loaded = session.context.load_file(dir.join("main.fs"));
+        fs::remove_dir_all(&dir).unwrap();
+        loaded.unwrap();
+        assert_eq!(session.compiler().sm.st.number_stack, vec![3, 4, 7, 1, 2]);
+    }
+
+    #[test]
+    fn require_loads_again_once_its_words_are_gone() {
+        let dir = std::env::temp_dir().join(format!("require-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.fs"), "REQUIRE bee.fs\n: main bee ;\n").unwrap();
+        fs::write(dir.join("bee.fs"), ": bee 1 ;\n").unwrap();
+        let load = format!("\\l {}", dir.join("main.fs").display());
+
+        let mut session = ReplSession::new();
+        for undo in ["\\undo", "\\forget bee", "\\rollback m"] {
+            session.process_line("\\marker m").unwrap();
+            session.process_line(&load).unwrap();
+            assert!(session.compiler().word_addresses.contains_key("bee"));
+            session.process_line(undo).unwrap();
+            assert!(!session.compiler().word_addresses.contains_key("bee"));
+        }
+        session.process_line(&load).unwrap();
+        assert!(session.compiler().word_addresses.contains_key("bee"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn included_files_are_watched() {
+        let dir = std::env::temp_dir().join(format!("watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.fs"), "REQUIRE bee.fs\n: main bee ;\n").unwrap();
+        fs::write(dir.join("bee.fs"), ": bee 1 ;\n").unwrap();
+
+        let mut session = ReplSession::new();
+        session
+            .process_line(&format!("\\watch {}", dir.join("main.fs").display()))
+            .unwrap();
+        session.process_line("7").unwrap();
+
+        fs::write(dir.join("bee.fs"), ": bee 2 ;\n").unwrap();
+        // Make sure the change shows however coarse the file system's clock is
+        fs::File::options()
+            .write(true)
+            .open(dir.join("bee.fs"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        session.reload_watched().unwrap();
+        session.process_line("main").unwrap();
+        assert_eq!(session.compiler().sm.st.number_stack, vec![7, 2]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_errors_point_at_the_unknown_word() {
+        let path = std::env::temp_dir().join(format!("locate-{}.fs", std::process::id()));
+        fs::write(&path, ": I 1 ;\n: predefined1 1 POP ;\n").unwrap();
+
+        let mut session = ReplSession::new();
+        let err = session.context.load_file(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        match err {
+            ForthInteractiveError::InSource { location, .. } => {
+                assert_eq!((location.line, location.column), (2, 17));
+                assert_eq!(location.token, "POP");
+            }
+            err => panic!("expected a source location, got {}", err),
+        }
+    }
+
+    #[test]
+    fn trace_is_kept_when_the_line_fails() {
+        let mut session = ReplSession::new();
+        session.process_line("\\trace on").unwrap();
+        assert!(session.process_line("1 DROP DROP").is_err());
+        let trace = session.take_failed_output();
+        assert_eq!(trace.lines().len(), 3);
+        assert!(trace.lines()[2].contains("DROP"));
+        assert!(session.take_failed_output().is_empty());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::error::ForthInteractiveError;
+use crate::opcode::{compiled_length, parse_opcode, Opcode};
+use rust_forth_compiler::{ForthCompiler, GasLimit};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Version of the snapshot file format, bump it whenever the format changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const SNAPSHOT_HEADER: &str = "rust-forth-snapshot";
+
+/// The word compiled to move the compiler's append point during a restore
+const PAD_WORD: &str = "__snapshot_pad";
+
+/// Everything a session builds up in a ForthCompiler
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub word_definitions: HashMap<String, String>,
+    pub word_addresses: HashMap<String, usize>,
+    pub word_opcodes: HashMap<String, Vec<Opcode>>,
+    /// The compiled words, without whatever the last execution left behind
+    pub opcodes: Vec<Opcode>,
+    pub number_stack: Vec<i64>,
+}
+
+impl Snapshot {
+    pub fn capture(fc: &ForthCompiler) -> Snapshot {
+        Snapshot::capture_keeping(fc, std::iter::empty())
+    }
+
+    /// Capture the compiler, keeping the opcodes of words compiled at other
+    /// addresses too. After a `\revert` the newest compiled word may be an
+    /// earlier definition that the dictionary no longer points at.
+    pub fn capture_keeping<I>(fc: &ForthCompiler, addresses: I) -> Snapshot
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let length = compiled_length(
+            &fc.sm.st.opcodes,
+            fc.word_addresses.values().copied().chain(addresses),
+        );
+
+        Snapshot {
+            word_definitions: fc.word_definitions.clone(),
+            word_addresses: fc.word_addresses.clone(),
+            word_opcodes: fc.word_opcodes.clone(),
+            opcodes: fc.sm.st.opcodes[..length].to_vec(),
+            number_stack: fc.sm.st.number_stack.clone(),
+        }
+    }
+
+    /// Whether a compiler is still in this state, as far as anything entered
+    /// at the REPL can change it
+    pub fn matches(&self, fc: &ForthCompiler) -> bool {
+        self.number_stack == fc.sm.st.number_stack
+            && self.word_addresses == fc.word_addresses
+            && self.word_definitions == fc.word_definitions
+            && fc.sm.st.opcodes.starts_with(&self.opcodes)
+    }
+
+    /// Build a compiler holding exactly this state
+    pub fn restore(&self) -> Result<ForthCompiler, ForthInteractiveError> {
+        let mut fc = ForthCompiler::default();
+        let length = self.opcodes.len();
+
+        // ForthCompiler keeps the position it compiles new words at to itself,
+        // and the only way to move it is to compile words. A padding word as
+        // long as all the compiled opcodes moves it to the end of them, as long
+        // as it lands at address 0 the way we think it does.
+        if length > 0 {
+            let pad = format!(": {} {};", PAD_WORD, "NOT ".repeat(length - 1));
+            fc.execute_string(&pad, GasLimit::Unlimited)?;
+            let padded = fc.word_opcodes.get(PAD_WORD).map(|opcodes| opcodes.len());
+            if fc.word_addresses.get(PAD_WORD) != Some(&0) || padded != Some(length) {
+                return Err(ForthInteractiveError::SnapshotMismatch(format!(
+                    "couldn't line the compiler up with the {} compiled opcodes",
+                    length
+                )));
+            }
+        }
+
+        fc.sm.st.opcodes = self.opcodes.clone();
+        fc.sm.st.number_stack = self.number_stack.clone();
+        fc.word_definitions = self.word_definitions.clone();
+        fc.word_addresses = self.word_addresses.clone();
+        fc.word_opcodes = self.word_opcodes.clone();
+        Ok(fc)
+    }
+
+    /// Put a compiler back into this state, keeping its trap handlers and the
+    /// `>R` stack, which a snapshot doesn't hold
+    pub fn restore_into(&self, fc: &mut ForthCompiler) -> Result<(), ForthInteractiveError> {
+        let mut restored = self.restore()?;
+        restored.sm.trap_handlers = std::mem::take(&mut fc.sm.trap_handlers);
+        restored.sm.st.scratch_stack = std::mem::take(&mut fc.sm.st.scratch_stack);
+        *fc = restored;
+        Ok(())
+    }
+
+    /// Write the snapshot out, one item per line so it diffs well
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ForthInteractiveError> {
+        let mut text = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(text, "{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION);
+
+        let stack: Vec<String> = self.number_stack.iter().map(|n| n.to_string()).collect();
+        let _ = writeln!(text, "number_stack {}", stack.join(" "));
+        for op in self.opcodes.iter() {
+            let _ = writeln!(text, "opcode {:?}", op);
+        }
+
+        let mut words: Vec<&String> = self.word_addresses.keys().collect();
+        words.sort_by_key(|w| self.word_addresses[*w]);
+        for w in words {
+            let _ = writeln!(text, "word {} {}", w, self.word_addresses[w]);
+            if let Some(definition) = self.word_definitions.get(w) {
+                let _ = writeln!(text, "definition {} {}", w, escape(definition));
+            }
+            if let Some(opcodes) = self.word_opcodes.get(w) {
+                let opcodes: Vec<String> = opcodes.iter().map(|op| format!("{:?}", op)).collect();
+                let _ = writeln!(text, "word_opcodes {} {}", w, opcodes.join(" "));
+            }
+        }
+
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, ForthInteractiveError> {
+        let text = match fs::read_to_string(path.as_ref()) {
+            Ok(text) => text,
+            Err(error) => {
+                return Err(ForthInteractiveError::CouldNotReadFile {
+                    path: path.as_ref().display().to_string(),
+                    error,
+                });
+            }
+        };
+        let mut lines = text.lines().enumerate();
+
+        let header = lines.next().map(|(_, l)| l).unwrap_or_default();
+        match header.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [SNAPSHOT_HEADER, version] if *version == SNAPSHOT_VERSION.to_string() => (),
+            [SNAPSHOT_HEADER, version] => {
+                return Err(ForthInteractiveError::SnapshotVersion {
+                    found: (*version).to_owned(),
+                    expected: SNAPSHOT_VERSION,
+                });
+            }
+            _ => return Err(invalid(1, "not a rust-forth snapshot")),
+        }
+
+        let mut snapshot = Snapshot::default();
+        for (i, line) in lines {
+            let line_number = i + 1;
+            let (key, rest) = split_first_word(line);
+            match key {
+                "" => (),
+                "number_stack" => {
+                    for n in rest.split_whitespace() {
+                        let n = n
+                            .parse::<i64>()
+                            .map_err(|_| invalid(line_number, "number expected"))?;
+                        snapshot.number_stack.push(n);
+                    }
+                }
+                "opcode" => snapshot.opcodes.push(
+                    parse_opcode(rest.trim())
+                        .ok_or_else(|| invalid(line_number, "unknown opcode"))?,
+                ),
+                "word" => {
+                    let (name, address) = split_first_word(rest);
+                    let address = address
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|_| invalid(line_number, "address expected"))?;
+                    snapshot.word_addresses.insert(name.to_owned(), address);
+                }
+                "definition" => {
+                    let (name, definition) = split_first_word(rest);
+                    snapshot
+                        .word_definitions
+                        .insert(name.to_owned(), unescape(definition));
+                }
+                "word_opcodes" => {
+                    let (name, opcodes) = split_first_word(rest);
+                    let opcodes = opcodes
+                        .split_whitespace()
+                        .map(parse_opcode)
+                        .collect::<Option<Vec<Opcode>>>()
+                        .ok_or_else(|| invalid(line_number, "unknown opcode"))?;
+                    snapshot.word_opcodes.insert(name.to_owned(), opcodes);
+                }
+                _ => return Err(invalid(line_number, "unknown entry")),
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+fn invalid(line: usize, message: &str) -> ForthInteractiveError {
+    ForthInteractiveError::InvalidSnapshot {
+        line,
+        message: message.to_owned(),
+    }
+}
+
+fn split_first_word(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    }
+}
+
+/// Definitions entered over several lines keep their newlines, store them on one
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::compiler;
+
+    #[test]
+    fn restore_lines_up_every_length() {
+        for (source, length) in [(": x ;", 1), (": x 1 ;", 2), (": x 1 2 ;", 3)] {
+            let snapshot = Snapshot::capture(&compiler(source));
+            assert_eq!(snapshot.opcodes.len(), length);
+
+            let mut fc = snapshot.restore().unwrap();
+            fc.execute_string(": y 5 ; x y", GasLimit::Unlimited)
+                .unwrap();
+            assert_eq!(fc.word_addresses["y"], length, "after {}", source);
+            assert_eq!(fc.sm.st.number_stack.last(), Some(&5));
+            assert!(!fc.word_addresses.contains_key(PAD_WORD));
+        }
+    }
+
+    #[test]
+    fn restore_empty() {
+        let mut fc = Snapshot::default().restore().unwrap();
+        fc.execute_string(": y 5 ; y", GasLimit::Unlimited).unwrap();
+        assert_eq!(fc.word_addresses["y"], 0);
+        assert_eq!(fc.sm.st.number_stack, vec![5]);
+    }
+
+    #[test]
+    fn restore_matches_captured_state() {
+        let fc = compiler(": dbl DUP ADD ; : quad dbl dbl ; 3 quad 7");
+        let snapshot = Snapshot::capture(&fc);
+        let restored = snapshot.restore().unwrap();
+        assert_eq!(restored.word_addresses, fc.word_addresses);
+        assert_eq!(restored.sm.st.opcodes, snapshot.opcodes);
+        assert_eq!(restored.sm.st.number_stack, vec![12, 7]);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut fc = compiler(": greet 1 ;\n: twice greet greet ;\n: multi 1\n2 ;\n-4");
+        // Definitions can hold backslashes and newlines
+        fc.word_definitions
+            .insert("greet".to_owned(), "a \\ b \n c".to_owned());
+        let snapshot = Snapshot::capture(&fc);
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.txt", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.word_definitions, snapshot.word_definitions);
+        assert_eq!(loaded.word_addresses, snapshot.word_addresses);
+        assert_eq!(loaded.word_opcodes, snapshot.word_opcodes);
+        assert_eq!(loaded.opcodes, snapshot.opcodes);
+        assert_eq!(loaded.number_stack, snapshot.number_stack);
+    }
+
+    #[test]
+    fn load_refuses_other_versions() {
+        let path = std::env::temp_dir().join(format!("snapshot-v-{}.txt", std::process::id()));
+        fs::write(&path, format!("{} 999\n", SNAPSHOT_HEADER)).unwrap();
+        let result = Snapshot::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(ForthInteractiveError::SnapshotVersion { .. })
+        ));
+    }
+}
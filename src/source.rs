@@ -0,0 +1,535 @@
+use crate::vocabulary::{BUILTIN_WORDS, CONTROL_FLOW_WORDS};
+
+/// A whitespace separated word of Forth source and the byte offset it starts at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'s> {
+    pub text: &'s str,
+    pub start: usize,
+}
+
+impl<'s> Token<'s> {
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+/// What a token means to the compiler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Number,
+    /// `:` or `;`
+    Definition,
+    /// The name following a `:`
+    DefinedName,
+    ControlFlow,
+    Builtin,
+    /// A word in the dictionary
+    UserWord,
+    Unknown,
+}
+
+/// Split Forth source into tokens the same way the compiler does, on whitespace
+/// and after a `:` or `;`. The compiler ignores `\` and `( )` comments and the
+/// string after a word ending in `"`, so they are left out.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start();
+        let start = source.len() - rest.len();
+        let end = match rest.chars().next() {
+            None => break,
+            Some('\\') => {
+                rest = past(rest, &['\n', '\r']);
+                continue;
+            }
+            Some('(') => {
+                rest = past(rest, &[')']);
+                continue;
+            }
+            Some(':') | Some(';') => 1,
+            Some(_) => rest
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(rest.len()),
+        };
+
+        let text = &rest[..end];
+        rest = &rest[end..];
+        if text.ends_with('"') {
+            rest = past(rest, &['"']);
+            continue;
+        }
+        tokens.push(Token { text, start });
+    }
+    tokens
+}
+
+/// The text after the first of the `ends`, nothing if there isn't one
+fn past<'s>(text: &'s str, ends: &[char]) -> &'s str {
+    match text.find(ends) {
+        Some(i) => &text[i + 1..],
+        None => "",
+    }
+}
+
+/// Work out what every token in the source is, `is_user_word` says whether a
+/// word is already in the dictionary. Words defined earlier in the same source
+/// count as known.
+pub fn classify<F>(source: &str, is_user_word: F) -> Vec<(Token<'_>, TokenKind)>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut defined_here: Vec<&str> = Vec::new();
+    let mut after_colon = false;
+
+    tokenize(source)
+        .into_iter()
+        .map(|token| {
+            let kind = if after_colon {
+                after_colon = false;
+                defined_here.push(token.text);
+                TokenKind::DefinedName
+            } else if token.text == ":" {
+                after_colon = true;
+                TokenKind::Definition
+            } else if token.text == ";" {
+                TokenKind::Definition
+            } else if token.text.parse::<i64>().is_ok() {
+                TokenKind::Number
+            } else if CONTROL_FLOW_WORDS.contains(&token.text) {
+                TokenKind::ControlFlow
+            } else if BUILTIN_WORDS.contains(&token.text) {
+                TokenKind::Builtin
+            } else if is_user_word(token.text) || defined_here.contains(&token.text) {
+                TokenKind::UserWord
+            } else {
+                TokenKind::Unknown
+            };
+            (token, kind)
+        })
+        .collect()
+}
+
+/// Whether the source defines any words
+pub fn defines_words(source: &str) -> bool {
+    tokenize(source)
+        .iter()
+        .any(|t| t.text == ":" || t.text == ";")
+}
+
+/// The Forth text a word was defined with. The compiler keeps a definition as
+/// the Debug form of its tokens, `[Number(1), Command("DUP")]`, so turn that
+/// back into source. Anything not in that form is taken to be source already.
+///
+/// A `\` comment is followed by a newline, so the text can still be closed
+/// with a `;` after it.
+pub fn definition_text(definition: &str) -> String {
+    match DebugTokens::new(definition).source() {
+        Some(text) => text,
+        None => definition.trim().to_owned(),
+    }
+}
+
+/// Reads the Debug form of a list of rust-forth-tokenizer tokens
+struct DebugTokens<'d> {
+    rest: &'d str,
+}
+
+impl<'d> DebugTokens<'d> {
+    fn new(text: &'d str) -> DebugTokens<'d> {
+        DebugTokens { rest: text.trim() }
+    }
+
+    fn source(&mut self) -> Option<String> {
+        self.expect("[")?;
+        let mut text = String::new();
+        if self.expect("]").is_some() {
+            return self.at_end(text);
+        }
+        loop {
+            let kind = self.identifier()?;
+            self.expect("(")?;
+            let piece = match kind {
+                "Number" => self.number()?.to_string(),
+                "Command" => self.string()?,
+                "StringCommand" => {
+                    let command = self.string()?;
+                    self.expect(", ")?;
+                    format!("{} {}\"", command, self.string()?)
+                }
+                "DropLineComment" => format!("{}\n", self.string()?),
+                "ParenthesizedRemark" => format!("{})", self.string()?),
+                _ => return None,
+            };
+            self.expect(")")?;
+
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push(' ');
+            }
+            text.push_str(&piece);
+
+            if self.expect("]").is_some() {
+                return self.at_end(text);
+            }
+            self.expect(", ")?;
+        }
+    }
+
+    fn at_end(&self, text: String) -> Option<String> {
+        if self.rest.is_empty() {
+            Some(text)
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, prefix: &str) -> Option<()> {
+        self.rest = self.rest.strip_prefix(prefix)?;
+        Some(())
+    }
+
+    fn identifier(&mut self) -> Option<&'d str> {
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(self.rest.len());
+        let (identifier, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(identifier).filter(|i| !i.is_empty())
+    }
+
+    fn number(&mut self) -> Option<i64> {
+        let end = self
+            .rest
+            .char_indices()
+            .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && *c == '-')))
+            .map(|(i, _)| i)
+            .unwrap_or(self.rest.len());
+        let (number, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        number.parse().ok()
+    }
+
+    /// A string with the escapes `str`'s Debug uses
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Some(string);
+                }
+                '\\' => {
+                    let escaped = match chars.next()?.1 {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        '0' => '\0',
+                        'u' => {
+                            let hex: String = chars
+                                .by_ref()
+                                .map(|(_, c)| c)
+                                .skip_while(|c| *c == '{')
+                                .take_while(|c| *c != '}')
+                                .collect();
+                            char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                        }
+                        c => c,
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+        None
+    }
+}
+
+/// How far through its constructs a piece of Forth source is
+#[derive(Debug, Clone, PartialEq)]
+pub enum Nesting {
+    /// Every definition and control structure is closed
+    Complete,
+    /// These constructs are still open, innermost last
+    Open(Vec<String>),
+    /// Something is closed that was never opened, or closed by the wrong word
+    Invalid(String),
+}
+
+/// The word that closes a construct and the words that may appear inside it
+fn closers(opener: &str) -> &'static [&'static str] {
+    match opener {
+        ":" => &[";"],
+        "IF" => &["ELSE", "THEN"],
+        "ELSE" => &["THEN"],
+        "BEGIN" => &["AGAIN", "UNTIL", "WHILE"],
+        "WHILE" => &["REPEAT"],
+        "DO" => &["LOOP", "+LOOP"],
+        _ => &[],
+    }
+}
+
+/// Check that `:`/`;`, `IF`/`THEN`, `BEGIN`/`AGAIN`, `BEGIN`/`WHILE`/`REPEAT`
+/// and `DO`/`LOOP` are balanced
+pub fn nesting(source: &str) -> Nesting {
+    let mut open: Vec<&str> = Vec::new();
+    let mut after_colon = false;
+
+    for token in tokenize(source) {
+        if after_colon {
+            // The name being defined, whatever it looks like
+            after_colon = false;
+            continue;
+        }
+        match token.text {
+            ":" => {
+                if !open.is_empty() {
+                    return Nesting::Invalid("`:` inside another definition".to_owned());
+                }
+                after_colon = true;
+                open.push(":");
+            }
+            "IF" | "BEGIN" | "DO" => open.push(token.text),
+            ";" | "ELSE" | "THEN" | "AGAIN" | "UNTIL" | "WHILE" | "REPEAT" | "LOOP" | "+LOOP" => {
+                match open.pop() {
+                    Some(opener) if closers(opener).contains(&token.text) => (),
+                    Some(opener) => {
+                        return Nesting::Invalid(format!(
+                            "`{}` found while `{}` is still open",
+                            token.text, opener
+                        ));
+                    }
+                    None => {
+                        return Nesting::Invalid(format!(
+                            "`{}` without anything to close",
+                            token.text
+                        ));
+                    }
+                }
+                // ELSE closes the IF branch and opens the ELSE branch, WHILE
+                // does the same for the end of a BEGIN loop
+                if token.text == "ELSE" || token.text == "WHILE" {
+                    open.push(token.text);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if open.is_empty() {
+        Nesting::Complete
+    } else {
+        Nesting::Open(open.into_iter().map(|o| o.to_owned()).collect())
+    }
+}
+
+/// A run of whole lines from a source file that closes every construct it opens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Statement<'s> {
+    pub text: &'s str,
+    /// Byte offset of the statement in the source
+    pub start: usize,
+}
+
+/// Split a source file into statements that can be executed one after another
+pub fn statements(source: &str) -> Vec<Statement<'_>> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+
+    for line in source.split_inclusive('\n') {
+        end += line.len();
+        let text = &source[start..end];
+        // Keep going while something is open, anything else is for the compiler to judge
+        if let Nesting::Open(_) = nesting(text) {
+            continue;
+        }
+        if !text.trim().is_empty() {
+            statements.push(Statement { text, start });
+        }
+        start = end;
+    }
+    if !source[start..].trim().is_empty() {
+        statements.push(Statement {
+            text: &source[start..],
+            start,
+        });
+    }
+
+    statements
+}
+
+/// A piece of a statement, either Forth for the compiler or a file to pull in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Directive<'s> {
+    Forth(Statement<'s>),
+    /// `INCLUDE file` loads the file every time, `REQUIRE file` only if it
+    /// hasn't been loaded already. The token's offset is in the whole source.
+    Include {
+        file: Token<'s>,
+        once: bool,
+    },
+}
+
+/// Split a statement at every `INCLUDE` and `REQUIRE` outside a definition.
+/// One without a file name is left for the compiler to complain about.
+pub fn directives(statement: Statement<'_>) -> Vec<Directive<'_>> {
+    let tokens = tokenize(statement.text);
+    let mut directives = Vec::new();
+    let mut forth_start = 0;
+    let mut in_definition = false;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = tokens[i];
+        let once = match token.text.to_ascii_uppercase().as_str() {
+            ":" => {
+                in_definition = true;
+                None
+            }
+            ";" => {
+                in_definition = false;
+                None
+            }
+            "INCLUDE" if !in_definition => Some(false),
+            "REQUIRE" if !in_definition => Some(true),
+            _ => None,
+        };
+
+        match (once, tokens.get(i + 1)) {
+            (Some(once), Some(file)) => {
+                let forth = &statement.text[forth_start..token.start];
+                if !forth.trim().is_empty() {
+                    directives.push(Directive::Forth(Statement {
+                        text: forth,
+                        start: statement.start + forth_start,
+                    }));
+                }
+                directives.push(Directive::Include {
+                    file: Token {
+                        text: file.text,
+                        start: statement.start + file.start,
+                    },
+                    once,
+                });
+                forth_start = file.end();
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+
+    let forth = &statement.text[forth_start..];
+    if !forth.trim().is_empty() {
+        directives.push(Directive::Forth(Statement {
+            text: forth,
+            start: statement.start + forth_start,
+        }));
+    }
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        classify(source, |w| w == "Eric")
+            .into_iter()
+            .map(|(_, kind)| kind)
+            .collect()
+    }
+
+    #[test]
+    fn classify_uses_the_compilers_words() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("1+ = 2DROP <> POP DEC + >R Eric WHILE"),
+            vec![
+                Builtin,
+                Builtin,
+                Builtin,
+                Builtin,
+                Unknown,
+                Unknown,
+                Unknown,
+                Unknown,
+                UserWord,
+                ControlFlow
+            ]
+        );
+        assert_eq!(
+            kinds(": w 1 w ;"),
+            vec![Definition, DefinedName, Number, UserWord, Definition]
+        );
+    }
+
+    fn texts(source: &str) -> Vec<&str> {
+        tokenize(source).into_iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn tokenize_skips_what_the_compiler_ignores() {
+        assert_eq!(
+            texts(": sq ( n -- n*n ) DUP MUL ; \\ square it\n2 sq"),
+            vec![":", "sq", "DUP", "MUL", ";", "2", "sq"]
+        );
+        assert_eq!(texts(":sq 1 ;"), vec![":", "sq", "1", ";"]);
+        assert_eq!(texts(".\" say IF \" 1 ( open"), vec!["1"]);
+
+        let tokens = tokenize("( a ) 1\n  DUP");
+        assert_eq!((tokens[0].start, tokens[1].start), (6, 10));
+    }
+
+    #[test]
+    fn nesting_of_control_structures() {
+        assert_eq!(
+            nesting(": w BEGIN DUP WHILE 1 SUB REPEAT ;"),
+            Nesting::Complete
+        );
+        assert_eq!(
+            nesting(": pos DUP IF DROP 1 ELSE 0 THEN ; : sum 0 5 0 DO I ADD LOOP ;"),
+            Nesting::Complete
+        );
+        assert_eq!(
+            nesting(": w BEGIN DUP WHILE"),
+            Nesting::Open(vec![":".to_owned(), "WHILE".to_owned()])
+        );
+        assert_eq!(
+            nesting(": w ( IF ) 1 \\ DO\n"),
+            Nesting::Open(vec![":".to_owned()])
+        );
+        assert!(matches!(
+            nesting(": w BEGIN 1 REPEAT ;"),
+            Nesting::Invalid(_)
+        ));
+        assert!(matches!(nesting("THEN"), Nesting::Invalid(_)));
+        assert!(matches!(nesting(": a : b ;"), Nesting::Invalid(_)));
+    }
+
+    #[test]
+    fn definition_text_from_tokens() {
+        assert_eq!(
+            definition_text(r#"[Number(-1), Command("DUP"), Command("2DUP")]"#),
+            "-1 DUP 2DUP"
+        );
+        assert_eq!(definition_text("[]"), "");
+        assert_eq!(
+            definition_text(
+                r#"[ParenthesizedRemark("( n -- n "), StringCommand(".\"", "say \"hi\""), DropLineComment("\\ done")]"#
+            ),
+            "( n -- n ) .\" say \"hi\"\" \\ done\n"
+        );
+    }
+
+    #[test]
+    fn definition_text_keeps_source() {
+        assert_eq!(definition_text(" 1 DUP "), "1 DUP");
+        assert_eq!(
+            definition_text("[Number(1), Nonsense]"),
+            "[Number(1), Nonsense]"
+        );
+    }
+}
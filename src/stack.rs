@@ -0,0 +1,98 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+/// How numbers on the stack are shown
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NumberBase {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+    /// Printable ASCII as a quoted character, anything else in decimal
+    Char,
+}
+
+impl NumberBase {
+    pub fn parse(name: &str) -> Option<NumberBase> {
+        match name.to_ascii_lowercase().as_str() {
+            "dec" | "decimal" => Some(NumberBase::Decimal),
+            "hex" | "hexadecimal" => Some(NumberBase::Hex),
+            "bin" | "binary" => Some(NumberBase::Binary),
+            "char" | "chars" => Some(NumberBase::Char),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, n: i64) -> String {
+        let sign = if n < 0 { "-" } else { "" };
+        match self {
+            NumberBase::Decimal => n.to_string(),
+            NumberBase::Hex => format!("{}0x{:X}", sign, n.unsigned_abs()),
+            NumberBase::Binary => format!("{}0b{:b}", sign, n.unsigned_abs()),
+            NumberBase::Char => match u8::try_from(n) {
+                Ok(c) if c.is_ascii_graphic() || c == b' ' => format!("'{}'", c as char),
+                _ => n.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for NumberBase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NumberBase::Decimal => "dec",
+            NumberBase::Hex => "hex",
+            NumberBase::Binary => "bin",
+            NumberBase::Char => "char",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The stack on one line the way gforth's `.s` shows it, the depth first and
+/// the top of the stack last
+pub fn format_stack(number_stack: &[i64], base: NumberBase) -> String {
+    if number_stack.is_empty() {
+        return "<0> empty".to_owned();
+    }
+    let numbers: Vec<String> = number_stack.iter().map(|n| base.format(*n)).collect();
+    format!("<{}> {} <- top", number_stack.len(), numbers.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_in_each_base() {
+        let stack = [0, 10, -255, 65, 7];
+        for (base, shown) in [
+            (NumberBase::Decimal, "<5> 0 10 -255 65 7 <- top"),
+            (NumberBase::Hex, "<5> 0x0 0xA -0xFF 0x41 0x7 <- top"),
+            (
+                NumberBase::Binary,
+                "<5> 0b0 0b1010 -0b11111111 0b1000001 0b111 <- top",
+            ),
+            (NumberBase::Char, "<5> 0 10 -255 'A' 7 <- top"),
+        ] {
+            assert_eq!(format_stack(&stack, base), shown, "{}", base);
+            assert_eq!(NumberBase::parse(&base.to_string()), Some(base));
+        }
+        assert_eq!(format_stack(&[], NumberBase::Hex), "<0> empty");
+        assert_eq!(NumberBase::Hex.format(i64::MIN), "-0x8000000000000000");
+        assert_eq!(NumberBase::Char.format(32), "' '");
+    }
+
+    #[test]
+    fn base_names() {
+        for (name, base) in [
+            ("decimal", Some(NumberBase::Decimal)),
+            ("HEX", Some(NumberBase::Hex)),
+            ("binary", Some(NumberBase::Binary)),
+            ("chars", Some(NumberBase::Char)),
+            ("octal", None),
+        ] {
+            assert_eq!(NumberBase::parse(name), base, "{}", name);
+        }
+    }
+}
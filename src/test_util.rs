@@ -0,0 +1,10 @@
+//! Helpers shared by the unit tests
+
+use rust_forth_compiler::{ForthCompiler, GasLimit};
+
+/// A compiler that has already run some Forth source
+pub fn compiler(source: &str) -> ForthCompiler {
+    let mut fc = ForthCompiler::default();
+    fc.execute_string(source, GasLimit::Unlimited).unwrap();
+    fc
+}
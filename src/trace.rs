@@ -0,0 +1,84 @@
+use crate::debugger::Observer;
+use crate::error::ForthInteractiveError;
+use crate::machine::Machine;
+use crate::opcode::WordMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Logs every opcode run, with the program counter, the word it belongs to and
+/// the number stack before it runs
+pub struct Trace {
+    /// None when the log is printed with the rest of the session's output
+    writer: Option<Box<dyn Write>>,
+    /// Lines waiting to be printed
+    lines: Vec<String>,
+    /// Where the log is going, for showing the user
+    destination: String,
+}
+
+impl Trace {
+    /// Log to a file, replacing anything already in it
+    pub fn to_file<P: AsRef<Path>>(path: P) -> Result<Trace, ForthInteractiveError> {
+        let file = match File::create(path.as_ref()) {
+            Ok(file) => file,
+            Err(error) => {
+                return Err(ForthInteractiveError::CouldNotWriteFile {
+                    path: path.as_ref().display().to_string(),
+                    error,
+                });
+            }
+        };
+        Ok(Trace {
+            writer: Some(Box::new(BufWriter::new(file))),
+            lines: Vec::new(),
+            destination: path.as_ref().display().to_string(),
+        })
+    }
+
+    /// Log to the session's output, the lines are collected until taken
+    pub fn to_output() -> Trace {
+        Trace {
+            writer: None,
+            lines: Vec::new(),
+            destination: "stdout".to_owned(),
+        }
+    }
+
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    pub fn flush(&mut self) -> Result<(), ForthInteractiveError> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The lines logged since they were last taken, when logging to the output
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+}
+
+impl Observer for Trace {
+    fn observe(&mut self, machine: &Machine, words: &WordMap) -> Result<(), ForthInteractiveError> {
+        let op = match machine.current() {
+            Some(op) => format!("{:?}", op),
+            None => return Ok(()),
+        };
+        let line = format!(
+            "{:>6}  {:<16}{:<16}{:?}",
+            machine.pc,
+            words.word_at(machine.pc).unwrap_or("-"),
+            op,
+            machine.number_stack
+        );
+        match self.writer.as_mut() {
+            Some(writer) => writeln!(writer, "{}", line)?,
+            None => self.lines.push(line),
+        }
+        Ok(())
+    }
+}
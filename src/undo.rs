@@ -0,0 +1,121 @@
+use crate::history::WordHistory;
+use crate::snapshot::Snapshot;
+use crate::watch::LoadedFile;
+use std::collections::VecDeque;
+
+/// How many lines can be undone by default
+pub const DEFAULT_UNDO_LIMIT: usize = 50;
+
+/// The state of a session that undo puts back
+#[derive(Debug, Clone)]
+pub struct UndoState {
+    pub snapshot: Snapshot,
+    pub word_history: WordHistory,
+    pub loaded_files: Vec<LoadedFile>,
+}
+
+/// The states before the last few lines that changed anything, and the ones
+/// undone since, so they can be redone
+#[derive(Debug, Clone)]
+pub struct UndoHistory {
+    /// Most recent last
+    undo: VecDeque<UndoState>,
+    redo: Vec<UndoState>,
+    limit: usize,
+}
+
+impl Default for UndoHistory {
+    fn default() -> UndoHistory {
+        UndoHistory::new(DEFAULT_UNDO_LIMIT)
+    }
+}
+
+impl UndoHistory {
+    pub fn new(limit: usize) -> UndoHistory {
+        UndoHistory {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Remember the state before a change, anything undone can no longer be redone
+    pub fn record(&mut self, before: UndoState) {
+        self.redo.clear();
+        self.undo.push_back(before);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// The state to go back to, `current` is kept so it can be redone
+    pub fn undo(&mut self, current: UndoState) -> Option<UndoState> {
+        let state = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(state)
+    }
+
+    /// The state that was undone last, `current` is kept so it can be undone again
+    pub fn redo(&mut self, current: UndoState) -> Option<UndoState> {
+        let state = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(state)
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state told apart by its number stack
+    fn state(n: i64) -> UndoState {
+        UndoState {
+            snapshot: Snapshot {
+                number_stack: vec![n],
+                ..Snapshot::default()
+            },
+            word_history: WordHistory::default(),
+            loaded_files: Vec::new(),
+        }
+    }
+
+    fn stack(state: Option<UndoState>) -> Option<i64> {
+        state.map(|s| s.snapshot.number_stack[0])
+    }
+
+    #[test]
+    fn only_the_last_few_changes_are_kept() {
+        let mut history = UndoHistory::new(2);
+        for n in 1..=3 {
+            history.record(state(n));
+        }
+        assert_eq!(history.undo_len(), 2);
+
+        assert_eq!(stack(history.undo(state(4))), Some(3));
+        assert_eq!(stack(history.undo(state(3))), Some(2));
+        // The oldest change fell off the end
+        assert_eq!(stack(history.undo(state(2))), None);
+        assert_eq!(history.redo_len(), 2);
+
+        assert_eq!(stack(history.redo(state(2))), Some(3));
+        assert_eq!((history.undo_len(), history.redo_len()), (1, 1));
+
+        // A new change can't be redone past
+        history.record(state(5));
+        assert_eq!(stack(history.redo(state(6))), None);
+        assert_eq!(history.undo_len(), 2);
+    }
+}
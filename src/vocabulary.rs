@@ -0,0 +1,40 @@
+/// Words rust-forth-compiler understands without them being defined first,
+/// its intrinsic words
+pub const BUILTIN_WORDS: &[&str] = &[
+    "SWAP", "NOT", "ADD", "SUB", "MUL", "DIV", "DUP", "2DUP", "TRAP", "DROP", "2DROP", "2OVER",
+    "2SWAP", "1+", "1-", "2+", "2-", "2*", "2/", "I", "J", "AND", "=", "<>",
+];
+
+/// Words that only change the flow of control
+pub const CONTROL_FLOW_WORDS: &[&str] = &[
+    "DO", "LOOP", "+LOOP", "LEAVE", "BEGIN", "UNTIL", "WHILE", "REPEAT", "AGAIN", "IF", "ELSE",
+    "THEN",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_forth_compiler::{ForthCompiler, ForthError, GasLimit};
+
+    #[test]
+    fn builtin_words_are_the_compilers() {
+        for word in BUILTIN_WORDS.iter().chain(CONTROL_FLOW_WORDS) {
+            let mut fc = ForthCompiler::default();
+            let compiled = fc.execute_string(&format!(": w {} ;", word), GasLimit::Unlimited);
+            assert!(
+                !matches!(compiled, Err(ForthError::UnknownToken(_))),
+                "{}",
+                word
+            );
+        }
+        for word in ["POP", "INC", "DEC", "+", ">R"] {
+            let mut fc = ForthCompiler::default();
+            let compiled = fc.execute_string(&format!(": w {} ;", word), GasLimit::Unlimited);
+            assert!(
+                matches!(compiled, Err(ForthError::UnknownToken(_))),
+                "{}",
+                word
+            );
+        }
+    }
+}
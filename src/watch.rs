@@ -0,0 +1,123 @@
+use crate::snapshot::Snapshot;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A file loaded into the session, and the state from before it was first
+/// loaded so it can be loaded again from scratch
+#[derive(Debug, Clone)]
+pub struct LoadedFile {
+    pub path: PathBuf,
+    pub before: Snapshot,
+}
+
+/// A file being watched, and when it was last seen to change
+#[derive(Debug, Clone)]
+pub struct WatchedFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> WatchedFile {
+        WatchedFile {
+            path: path.as_ref().to_path_buf(),
+            modified: modified(path.as_ref()),
+        }
+    }
+
+    /// Whether the file changed since the last time this was asked
+    pub fn changed(&mut self) -> bool {
+        let now = modified(&self.path);
+        if now == self.modified {
+            return false;
+        }
+        self.modified = now;
+        true
+    }
+}
+
+/// A file that can't be read counts as changed once it can be again
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Files to reload whenever one of them changes, and the state to reload them
+/// on top of
+#[derive(Debug, Clone)]
+pub struct Watch {
+    /// In the order they are loaded
+    pub files: Vec<WatchedFile>,
+    /// The files they INCLUDE or REQUIRE, reloaded by reloading the files
+    /// above
+    pub included: Vec<WatchedFile>,
+    pub base: Snapshot,
+    /// How many of the session's loaded files are already in the base
+    pub base_files: usize,
+    /// Whether the base is an empty dictionary rather than the session before
+    /// the files were loaded
+    pub fresh: bool,
+}
+
+impl Watch {
+    /// Whether any of the files changed since the last check
+    pub fn changed(&mut self) -> bool {
+        // Every file is checked so none of them triggers a second reload later
+        let mut changed = false;
+        for f in self.files.iter_mut().chain(self.included.iter_mut()) {
+            changed |= f.changed();
+        }
+        changed
+    }
+}
+
+/// How the words in the dictionary changed across a reload
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WordChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl WordChanges {
+    /// Compare the definitions of every word before and after
+    pub fn between(
+        before: &HashMap<String, String>,
+        after: &HashMap<String, String>,
+    ) -> WordChanges {
+        let mut changes = WordChanges::default();
+        for (word, definition) in after.iter() {
+            match before.get(word) {
+                None => changes.added.push(word.clone()),
+                Some(old) if old.trim() != definition.trim() => changes.changed.push(word.clone()),
+                Some(_) => (),
+            }
+        }
+        changes.removed = before
+            .keys()
+            .filter(|w| !after.contains_key(*w))
+            .cloned()
+            .collect();
+
+        changes.added.sort();
+        changes.removed.sort();
+        changes.changed.sort();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One line per word, `+` for added, `-` for removed and `~` for changed
+    pub fn lines(&self) -> Vec<String> {
+        if self.is_empty() {
+            return vec!["No words changed".to_owned()];
+        }
+        let added = self.added.iter().map(|w| format!("+ {}", w));
+        let removed = self.removed.iter().map(|w| format!("- {}", w));
+        let changed = self.changed.iter().map(|w| format!("~ {}", w));
+        added.chain(removed).chain(changed).collect()
+    }
+}